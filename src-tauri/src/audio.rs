@@ -1,8 +1,12 @@
+use crate::audio_source::AudioSource;
+use crate::downmix::DownmixMode;
+use crate::ring_buffer::RingBuffer;
 use anyhow::{Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{FromSample, Sample, SizedSample, StreamConfig};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample, StreamConfig};
 use parking_lot::Mutex;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc};
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,23 +15,95 @@ pub struct AudioDeviceInfo {
     pub is_default: bool,
 }
 
+/// Stream parameters negotiated with the input device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample_format: SampleFormat,
+}
+
+impl AudioFormat {
+    /// Number of interleaved samples covering `duration_ms` of audio
+    pub fn samples_for_ms(&self, duration_ms: u64) -> usize {
+        (self.sample_rate as u64 * self.channels as u64 * duration_ms / 1000) as usize
+    }
+}
+
+/// Captured samples together with the format they were recorded in
+#[derive(Debug, Clone)]
+pub struct RecordedAudio {
+    /// Interleaved samples, `format.channels` per frame
+    pub samples: Vec<f32>,
+    pub format: AudioFormat,
+}
+
+/// How much audio the capture ring buffer holds
+/// Recordings longer than this keep only their most recent part
+const CAPTURE_CAPACITY_MS: u64 = 30_000;
+
 pub struct AudioRecorder {
-    samples: Arc<Mutex<Vec<f32>>>,
-    is_recording: Arc<Mutex<bool>>,
+    /// Always-on capture, filled whenever the stream is open
+    samples: Arc<Mutex<RingBuffer>>,
+    /// Ring position the current recording starts at, None when not recording
+    recording_from: Mutex<Option<u64>>,
+    /// Whether the stream was opened by `start_recording` and should close with it
+    close_on_stop: Mutex<bool>,
+    format: Arc<Mutex<Option<AudioFormat>>>,
     device_name: Option<String>,
+    /// Downmix mode per device name, `None` keys the system default device
+    downmix_modes: HashMap<Option<String>, DownmixMode>,
+    stream: Mutex<Option<StreamHandle>>,
+}
+
+/// Input stream owned by its dedicated audio thread
+struct StreamHandle {
+    stop_tx: mpsc::Sender<()>,
+    thread: std::thread::JoinHandle<()>,
 }
 
 impl AudioRecorder {
     pub fn new() -> Self {
         Self {
-            samples: Arc::new(Mutex::new(Vec::new())),
-            is_recording: Arc::new(Mutex::new(false)),
+            samples: Arc::new(Mutex::new(RingBuffer::new(0))),
+            recording_from: Mutex::new(None),
+            close_on_stop: Mutex::new(false),
+            format: Arc::new(Mutex::new(None)),
             device_name: None,
+            downmix_modes: HashMap::new(),
+            stream: Mutex::new(None),
         }
     }
 
-    pub fn set_device(&mut self, device_name: Option<String>) {
+    /// Select the input device, releasing the currently open one
+    /// An in-progress recording carries on from the new device
+    pub fn set_device(&mut self, device_name: Option<String>) -> Result<()> {
+        if device_name == self.device_name {
+            return Ok(());
+        }
+
+        let was_open = self.is_open();
+        self.close();
         self.device_name = device_name;
+
+        if was_open {
+            // Reopening resizes the ring for the new format, dropping the old device's samples
+            self.open()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_downmix_mode(&mut self, device_name: Option<String>, mode: DownmixMode) {
+        self.downmix_modes.insert(device_name, mode);
+    }
+
+    /// Downmix mode for the currently selected device
+    pub fn downmix_mode(&self) -> DownmixMode {
+        self.downmix_modes
+            .get(&self.device_name)
+            .copied()
+            .unwrap_or_default()
     }
 
     pub fn list_input_devices() -> Result<Vec<AudioDeviceInfo>> {
@@ -50,95 +126,124 @@ impl AudioRecorder {
         Ok(devices)
     }
 
-    pub fn start_recording(&self) -> Result<()> {
-        let host = cpal::default_host();
+    /// Open the input stream on its own audio thread
+    /// Capture runs into the ring buffer until `close`; opening an already open stream is a no-op
+    pub fn open(&self) -> Result<AudioFormat> {
+        let mut stream = self.stream.lock();
+        if stream.is_some() {
+            return self.format().context("Open stream has no format");
+        }
+
+        let (ready_tx, ready_rx) = mpsc::sync_channel(1);
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let device_name = self.device_name.clone();
+        let samples = Arc::clone(&self.samples);
 
-        let device = if let Some(ref device_name) = self.device_name {
-            // Find device by name
-            host.input_devices()?
-                .find(|d| d.name().ok().as_ref() == Some(device_name))
-                .context(format!("Device '{}' not found", device_name))?
-        } else {
-            // Use default device
-            host.default_input_device()
-                .context("No input device available")?
+        // cpal streams aren't Send on every platform, so the stream lives and dies on this thread
+        let thread = std::thread::Builder::new()
+            .name("audio-input".to_string())
+            .spawn(move || {
+                let input = match open_input_stream(device_name.as_deref(), samples) {
+                    Ok((input, format)) => {
+                        let _ = ready_tx.send(Ok(format));
+                        input
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                // Park until close() signals or the recorder is dropped
+                let _ = stop_rx.recv();
+                let _ = input.pause();
+            })
+            .context("Failed to spawn audio thread")?;
+
+        let format = match ready_rx.recv() {
+            Ok(Ok(format)) => format,
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                return Err(e);
+            }
+            Err(_) => {
+                let _ = thread.join();
+                return Err(anyhow::anyhow!("Audio thread exited before opening the stream"));
+            }
         };
 
-        let config = device
-            .default_input_config()
-            .context("Failed to get default input config")?;
+        *self.format.lock() = Some(format);
+        *stream = Some(StreamHandle { stop_tx, thread });
 
-        let samples = Arc::clone(&self.samples);
-        let is_recording = Arc::clone(&self.is_recording);
-
-        // Clear previous samples
-        samples.lock().clear();
-        *is_recording.lock() = true;
-
-        match config.sample_format() {
-            cpal::SampleFormat::I8 => self.run::<i8>(&device, &config.into(), samples, is_recording)?,
-            cpal::SampleFormat::I16 => self.run::<i16>(&device, &config.into(), samples, is_recording)?,
-            cpal::SampleFormat::I32 => self.run::<i32>(&device, &config.into(), samples, is_recording)?,
-            cpal::SampleFormat::F32 => self.run::<f32>(&device, &config.into(), samples, is_recording)?,
-            _ => return Err(anyhow::anyhow!("Unsupported sample format")),
+        Ok(format)
+    }
+
+    /// Stop and drop the input stream, releasing the device
+    pub fn close(&self) {
+        if let Some(handle) = self.stream.lock().take() {
+            let _ = handle.stop_tx.send(());
+            let _ = handle.thread.join();
         }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.stream.lock().is_some()
+    }
+
+    /// Start a recording that also includes up to `pre_roll_ms` of audio
+    /// captured before this call, if the stream was already open
+    pub fn start_recording(&self, pre_roll_ms: u64) -> Result<()> {
+        let was_open = self.is_open();
+        let format = self.open()?;
+        *self.close_on_stop.lock() = !was_open;
+
+        let start = {
+            let samples = self.samples.lock();
+            let pre_roll = (format.samples_for_ms(pre_roll_ms) as u64).min(samples.len() as u64);
+            // Stay on a frame boundary so channels don't swap
+            samples.end_position() - (pre_roll - pre_roll % format.channels.max(1) as u64)
+        };
+        *self.recording_from.lock() = Some(start);
 
         Ok(())
     }
 
-    pub fn stop_recording(&self) -> Vec<f32> {
-        *self.is_recording.lock() = false;
-        // Give the stream a moment to finish processing
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        let samples = self.samples.lock().clone();
-        samples
+    /// Stop recording and return the captured audio
+    /// The stream is released unless it was opened explicitly with `open`
+    /// Returns None if no stream has been opened yet
+    pub fn stop_recording(&self) -> Option<RecordedAudio> {
+        if std::mem::take(&mut *self.close_on_stop.lock()) {
+            // Joining the audio thread guarantees the callback has finished
+            self.close();
+        }
+
+        let from = self.recording_from.lock().take().unwrap_or(u64::MAX);
+        let samples = self.samples.lock().since(from);
+        let format = (*self.format.lock())?;
+        Some(RecordedAudio { samples, format })
+    }
+
+    /// Format of the most recently opened stream
+    pub fn format(&self) -> Option<AudioFormat> {
+        *self.format.lock()
     }
 
     pub fn is_recording(&self) -> bool {
-        *self.is_recording.lock()
+        self.recording_from.lock().is_some()
     }
 
-    /// Get a copy of current samples without stopping recording
+    /// Get a copy of the current recording without stopping it
     pub fn get_current_samples(&self) -> Vec<f32> {
-        self.samples.lock().clone()
-    }
-
-    fn run<T>(
-        &self,
-        device: &cpal::Device,
-        config: &StreamConfig,
-        samples: Arc<Mutex<Vec<f32>>>,
-        is_recording: Arc<Mutex<bool>>,
-    ) -> Result<()>
-    where
-        T: Sample + SizedSample,
-        f32: FromSample<T>,
-    {
-        let err_fn = |err| eprintln!("Error occurred on stream: {}", err);
-
-        let stream = device.build_input_stream(
-            config,
-            move |data: &[T], _: &cpal::InputCallbackInfo| {
-                if !*is_recording.lock() {
-                    return;
-                }
-
-                let mut samples = samples.lock();
-                for &sample in data.iter() {
-                    samples.push(sample.to_sample::<f32>());
-                }
-            },
-            err_fn,
-            None,
-        )?;
-
-        stream.play()?;
-
-        // Keep the stream alive by intentionally leaking it
-        // The is_recording flag controls whether samples are collected
-        std::mem::forget(stream);
+        let from = *self.recording_from.lock();
+        match from {
+            Some(from) => self.samples.lock().since(from),
+            None => Vec::new(),
+        }
+    }
 
-        Ok(())
+    /// The most recent `len` captured samples, whether recording or not
+    pub fn latest_samples(&self, len: usize) -> Vec<f32> {
+        self.samples.lock().latest(len)
     }
 
     pub fn save_to_wav(&self, samples: &[f32], sample_rate: u32, path: &str) -> Result<()> {
@@ -159,25 +264,49 @@ impl AudioRecorder {
         writer.finalize()?;
         Ok(())
     }
+}
 
-    pub fn convert_to_16khz_mono(&self, samples: &[f32], original_sample_rate: u32) -> Vec<f32> {
-        if original_sample_rate == 16000 {
-            return samples.to_vec();
-        }
+impl AudioSource for AudioRecorder {
+    fn open(&self) -> Result<()> {
+        AudioRecorder::open(self).map(|_| ())
+    }
+
+    fn close(&self) {
+        AudioRecorder::close(self)
+    }
 
-        let ratio = original_sample_rate as f32 / 16000.0;
-        let new_length = (samples.len() as f32 / ratio) as usize;
-        let mut resampled = Vec::with_capacity(new_length);
+    fn start(&self, pre_roll_ms: u64) -> Result<()> {
+        self.start_recording(pre_roll_ms)
+    }
 
-        for i in 0..new_length {
-            let pos = i as f32 * ratio;
-            let index = pos as usize;
-            if index < samples.len() {
-                resampled.push(samples[index]);
-            }
-        }
+    fn stop(&self) -> Option<RecordedAudio> {
+        self.stop_recording()
+    }
+
+    fn is_active(&self) -> bool {
+        self.is_recording()
+    }
+
+    fn format(&self) -> Option<AudioFormat> {
+        AudioRecorder::format(self)
+    }
+
+    fn read(&self) -> Vec<f32> {
+        self.get_current_samples()
+    }
+
+    fn latest(&self, len: usize) -> Vec<f32> {
+        self.latest_samples(len)
+    }
+
+    fn downmix_mode(&self) -> DownmixMode {
+        AudioRecorder::downmix_mode(self)
+    }
+}
 
-        resampled
+impl Drop for AudioRecorder {
+    fn drop(&mut self) {
+        self.close();
     }
 }
 
@@ -186,3 +315,74 @@ impl Default for AudioRecorder {
         Self::new()
     }
 }
+
+/// Resolve the device, build an input stream in its default config and start it
+fn open_input_stream(
+    device_name: Option<&str>,
+    samples: Arc<Mutex<RingBuffer>>,
+) -> Result<(cpal::Stream, AudioFormat)> {
+    let host = cpal::default_host();
+
+    let device = if let Some(device_name) = device_name {
+        // Find device by name
+        host.input_devices()?
+            .find(|d| d.name().ok().as_deref() == Some(device_name))
+            .context(format!("Device '{}' not found", device_name))?
+    } else {
+        // Use default device
+        host.default_input_device()
+            .context("No input device available")?
+    };
+
+    let config = device
+        .default_input_config()
+        .context("Failed to get default input config")?;
+
+    let sample_format = config.sample_format();
+    let stream_config: StreamConfig = config.into();
+
+    let stream = match sample_format {
+        SampleFormat::I8 => build_stream::<i8>(&device, &stream_config, samples.clone())?,
+        SampleFormat::I16 => build_stream::<i16>(&device, &stream_config, samples.clone())?,
+        SampleFormat::I32 => build_stream::<i32>(&device, &stream_config, samples.clone())?,
+        SampleFormat::F32 => build_stream::<f32>(&device, &stream_config, samples.clone())?,
+        _ => return Err(anyhow::anyhow!("Unsupported sample format: {:?}", sample_format)),
+    };
+
+    let format = AudioFormat {
+        sample_rate: stream_config.sample_rate.0,
+        channels: stream_config.channels,
+        sample_format,
+    };
+
+    // Size the ring for this device before any samples arrive
+    samples.lock().set_capacity(format.samples_for_ms(CAPTURE_CAPACITY_MS));
+
+    stream.play()?;
+
+    Ok((stream, format))
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    samples: Arc<Mutex<RingBuffer>>,
+) -> Result<cpal::Stream>
+where
+    T: Sample + SizedSample,
+    f32: FromSample<T>,
+{
+    let err_fn = |err| eprintln!("Error occurred on stream: {}", err);
+
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            let converted: Vec<f32> = data.iter().map(|&sample| sample.to_sample::<f32>()).collect();
+            samples.lock().push(&converted);
+        },
+        err_fn,
+        None,
+    )?;
+
+    Ok(stream)
+}
@@ -0,0 +1,333 @@
+//! Audio source module
+//! Abstracts where captured audio comes from so the pipeline can run without a microphone
+
+use crate::audio::{AudioFormat, RecordedAudio};
+use crate::downmix::{downmix, DownmixMode};
+use crate::resample::{resample, WHISPER_SAMPLE_RATE};
+use anyhow::{Context, Result};
+use cpal::SampleFormat;
+use parking_lot::Mutex;
+use std::path::Path;
+use std::time::Instant;
+
+/// A capture device or anything that can stand in for one
+pub trait AudioSource: Send {
+    /// Begin always-on capture without recording, so later recordings can include pre-roll
+    fn open(&self) -> Result<()>;
+
+    /// End capture and release the underlying device or clip
+    fn close(&self);
+
+    /// Begin recording, opening the source if needed
+    /// Up to `pre_roll_ms` of audio captured before the call is included if the source was open
+    fn start(&self, pre_roll_ms: u64) -> Result<()>;
+
+    /// Stop recording and return everything recorded since `start`
+    /// A source opened by `start` is closed again; one opened with `open` stays open
+    /// Returns None if the source was never opened
+    fn stop(&self) -> Option<RecordedAudio>;
+
+    /// Whether a recording is in progress
+    fn is_active(&self) -> bool;
+
+    /// Format of the interleaved samples returned by `read`
+    fn format(&self) -> Option<AudioFormat>;
+
+    /// Snapshot of the current recording
+    fn read(&self) -> Vec<f32>;
+
+    /// The most recent `len` captured samples, whether recording or not
+    fn latest(&self, len: usize) -> Vec<f32>;
+
+    /// How multichannel audio from this source is folded to mono
+    fn downmix_mode(&self) -> DownmixMode {
+        DownmixMode::default()
+    }
+
+    /// Downmix and resample captured audio to the 16 kHz mono Whisper expects
+    fn convert_to_16khz_mono(&self, samples: &[f32], format: &AudioFormat) -> Vec<f32> {
+        let samples = downmix(samples, format.channels, self.downmix_mode());
+        resample(&samples, format.sample_rate, WHISPER_SAMPLE_RATE)
+    }
+}
+
+/// How quickly a file source releases its samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Playback {
+    /// Samples become available at the clip's own sample rate, followed by silence
+    RealTime,
+    /// The whole clip is available as soon as the source starts
+    Instant,
+}
+
+/// Plays back a WAV file or in-memory clip as if it were being captured live
+pub struct FileSource {
+    samples: Vec<f32>,
+    format: AudioFormat,
+    playback: Playback,
+    state: Mutex<FileSourceState>,
+}
+
+#[derive(Default)]
+struct FileSourceState {
+    /// When playback began, None while closed
+    opened_at: Option<Instant>,
+    /// Sample offset the current recording starts at
+    recording_from: Option<usize>,
+    close_on_stop: bool,
+}
+
+impl FileSource {
+    pub fn from_samples(samples: Vec<f32>, format: AudioFormat, playback: Playback) -> Self {
+        Self {
+            samples,
+            format,
+            playback,
+            state: Mutex::new(FileSourceState::default()),
+        }
+    }
+
+    pub fn open_wav(path: &Path, playback: Playback) -> Result<Self> {
+        let reader = hound::WavReader::open(path)
+            .with_context(|| format!("Failed to open WAV file {}", path.display()))?;
+        let spec = reader.spec();
+
+        let (samples, sample_format) = match spec.sample_format {
+            hound::SampleFormat::Float => (
+                reader
+                    .into_samples::<f32>()
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("Failed to read WAV samples")?,
+                SampleFormat::F32,
+            ),
+            hound::SampleFormat::Int => {
+                let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                let samples = reader
+                    .into_samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / scale))
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("Failed to read WAV samples")?;
+                let sample_format = match spec.bits_per_sample {
+                    8 => SampleFormat::I8,
+                    16 => SampleFormat::I16,
+                    _ => SampleFormat::I32,
+                };
+                (samples, sample_format)
+            }
+        };
+
+        let format = AudioFormat {
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+            sample_format,
+        };
+
+        Ok(Self::from_samples(samples, format, playback))
+    }
+
+    /// Samples released so far, padded with silence once a real-time clip runs out
+    fn available(&self, opened_at: Instant) -> usize {
+        match self.playback {
+            Playback::Instant => self.samples.len(),
+            Playback::RealTime => {
+                let elapsed_ms = opened_at.elapsed().as_millis() as u64;
+                let channels = self.format.channels.max(1) as usize;
+                // Whole frames only, so channels stay aligned
+                self.format.samples_for_ms(elapsed_ms) / channels * channels
+            }
+        }
+    }
+
+    fn range(&self, from: usize, to: usize) -> Vec<f32> {
+        (from..to)
+            .map(|i| self.samples.get(i).copied().unwrap_or(0.0))
+            .collect()
+    }
+
+    fn recording(&self, state: &FileSourceState) -> Vec<f32> {
+        match (state.opened_at, state.recording_from) {
+            (Some(opened_at), Some(from)) => self.range(from, self.available(opened_at)),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl AudioSource for FileSource {
+    fn open(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        if state.opened_at.is_none() {
+            state.opened_at = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    fn close(&self) {
+        *self.state.lock() = FileSourceState::default();
+    }
+
+    fn start(&self, pre_roll_ms: u64) -> Result<()> {
+        let mut state = self.state.lock();
+        let from = match state.opened_at {
+            Some(opened_at) => {
+                let channels = self.format.channels.max(1) as usize;
+                let pre_roll = self.format.samples_for_ms(pre_roll_ms) / channels * channels;
+                self.available(opened_at).saturating_sub(pre_roll)
+            }
+            None => {
+                // Opened by this recording, so it starts at the top of the clip
+                state.opened_at = Some(Instant::now());
+                state.close_on_stop = true;
+                0
+            }
+        };
+        state.recording_from = Some(from);
+        Ok(())
+    }
+
+    fn stop(&self) -> Option<RecordedAudio> {
+        let mut state = self.state.lock();
+        state.opened_at?;
+
+        let samples = self.recording(&state);
+        state.recording_from = None;
+        if state.close_on_stop {
+            *state = FileSourceState::default();
+        }
+
+        Some(RecordedAudio {
+            samples,
+            format: self.format,
+        })
+    }
+
+    fn is_active(&self) -> bool {
+        self.state.lock().recording_from.is_some()
+    }
+
+    fn format(&self) -> Option<AudioFormat> {
+        Some(self.format)
+    }
+
+    fn read(&self) -> Vec<f32> {
+        self.recording(&self.state.lock())
+    }
+
+    fn latest(&self, len: usize) -> Vec<f32> {
+        let state = self.state.lock();
+        let Some(opened_at) = state.opened_at else {
+            return Vec::new();
+        };
+
+        let available = self.available(opened_at);
+        let channels = self.format.channels.max(1) as usize;
+        let len = len / channels * channels;
+        self.range(available.saturating_sub(len), available)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_wav(path: &Path, channels: u16, sample_rate: u32, frames: usize) {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for i in 0..frames {
+            for channel in 0..channels {
+                writer.write_sample(((i % 100) as i16) * 100 + channel as i16).unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn test_wav_format_and_instant_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stereo.wav");
+        write_wav(&path, 2, 44100, 4410);
+
+        let source = FileSource::open_wav(&path, Playback::Instant).unwrap();
+
+        let format = source.format().unwrap();
+        assert_eq!(format.sample_rate, 44100);
+        assert_eq!(format.channels, 2);
+        assert_eq!(format.sample_format, SampleFormat::I16);
+
+        assert!(source.read().is_empty());
+        source.start(500).unwrap();
+        let samples = source.read();
+        assert_eq!(samples.len(), 8820);
+        assert!((samples[3] - 101.0 / 32768.0).abs() < 1e-6);
+
+        let recorded = source.stop().unwrap();
+        assert_eq!(recorded.samples, samples);
+        assert!(!source.is_active());
+        // Opened by start, so stop closed it again
+        assert!(source.latest(10).is_empty());
+
+        // 100 ms at 16 kHz mono
+        assert_eq!(source.convert_to_16khz_mono(&recorded.samples, &format).len(), 1600);
+    }
+
+    #[test]
+    fn test_realtime_pacing_and_silence_tail() {
+        let format = AudioFormat {
+            sample_rate: 16000,
+            channels: 1,
+            sample_format: SampleFormat::F32,
+        };
+        let source = FileSource::from_samples(vec![0.5; 800], format, Playback::RealTime);
+
+        // Never more than the time since the start, however slowly the test runs
+        let started = Instant::now();
+        source.start(0).unwrap();
+        let released = source.read().len();
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        assert!(released <= format.samples_for_ms(elapsed_ms + 1));
+
+        // The clip runs out, followed by silence
+        let deadline = Instant::now() + std::time::Duration::from_secs(10);
+        let samples = loop {
+            let samples = source.read();
+            if samples.len() >= 1600 {
+                break samples;
+            }
+            assert!(Instant::now() < deadline, "only {} samples", samples.len());
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert!(samples[..800].iter().all(|&s| s == 0.5));
+        assert!(samples[800..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_pre_roll_when_open() {
+        let format = AudioFormat {
+            sample_rate: 16000,
+            channels: 2,
+            sample_format: SampleFormat::F32,
+        };
+        let samples: Vec<f32> = (0..32000).map(|i| i as f32).collect();
+        let source = FileSource::from_samples(samples, format, Playback::Instant);
+
+        source.open().unwrap();
+        assert_eq!(source.latest(11), (31990..32000).map(|i| i as f32).collect::<Vec<_>>());
+
+        // 100 ms of stereo pre-roll before the start
+        source.start(100).unwrap();
+        assert_eq!(source.read().len(), 3200);
+        assert_eq!(source.read()[0], 28800.0);
+
+        // Explicitly opened, so it stays open after the recording
+        source.stop().unwrap();
+        assert!(!source.is_active());
+        assert_eq!(source.latest(2).len(), 2);
+
+        source.close();
+        assert!(source.stop().is_none());
+    }
+}
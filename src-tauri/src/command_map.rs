@@ -0,0 +1,150 @@
+//! Command mapping module
+//! Maps transcribed phrases to workflow actions, with a separate phrase list per language
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Language whose mappings are used when the spoken language has none of its own
+pub const FALLBACK_LANGUAGE: &str = "en";
+
+/// An action and the phrases that trigger it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandMapping {
+    pub action: String,
+    /// Matched case-insensitively anywhere in the transcription
+    pub phrases: Vec<String>,
+}
+
+impl CommandMapping {
+    fn new(action: &str, phrases: &[&str]) -> Self {
+        Self {
+            action: action.to_string(),
+            phrases: phrases.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+/// Command mappings keyed by Whisper language code
+///
+/// Mappings are checked in order, so earlier actions win when several phrases match.
+#[derive(Debug, Clone)]
+pub struct CommandMap {
+    languages: HashMap<String, Vec<CommandMapping>>,
+}
+
+impl Default for CommandMap {
+    fn default() -> Self {
+        let mut languages = HashMap::new();
+        languages.insert(
+            "en".to_string(),
+            vec![
+                CommandMapping::new("greeting", &["hello", "hi"]),
+                CommandMapping::new("start_workflow", &["start", "begin"]),
+                CommandMapping::new("stop_workflow", &["stop", "end"]),
+                CommandMapping::new("status_check", &["status", "report"]),
+                CommandMapping::new("show_help", &["help"]),
+            ],
+        );
+        languages.insert(
+            "de".to_string(),
+            vec![
+                CommandMapping::new("greeting", &["hallo", "guten tag"]),
+                CommandMapping::new("start_workflow", &["starten", "start", "beginnen"]),
+                CommandMapping::new("stop_workflow", &["stoppen", "stopp", "beenden"]),
+                CommandMapping::new("status_check", &["status", "bericht"]),
+                CommandMapping::new("show_help", &["hilfe"]),
+            ],
+        );
+        languages.insert(
+            "ja".to_string(),
+            vec![
+                CommandMapping::new("greeting", &["こんにちは", "もしもし"]),
+                CommandMapping::new("start_workflow", &["開始", "スタート", "始め"]),
+                CommandMapping::new("stop_workflow", &["停止", "ストップ", "終了"]),
+                CommandMapping::new("status_check", &["ステータス", "状況", "報告"]),
+                CommandMapping::new("show_help", &["ヘルプ", "助けて"]),
+            ],
+        );
+        Self { languages }
+    }
+}
+
+impl CommandMap {
+    /// Replace the mappings for one language, or remove them when `mappings` is empty
+    pub fn set_language(&mut self, language: &str, mappings: Vec<CommandMapping>) {
+        let language = language.to_lowercase();
+        if mappings.is_empty() {
+            self.languages.remove(&language);
+        } else {
+            self.languages.insert(language, mappings);
+        }
+    }
+
+    pub fn language(&self, language: &str) -> Option<&[CommandMapping]> {
+        self.languages.get(&language.to_lowercase()).map(Vec::as_slice)
+    }
+
+    /// Every phrase of every language, ordered by language code
+    pub fn phrases(&self) -> Vec<String> {
+        let mut languages: Vec<_> = self.languages.iter().collect();
+        languages.sort_by_key(|(language, _)| language.as_str());
+
+        languages
+            .into_iter()
+            .flat_map(|(_, mappings)| mappings)
+            .flat_map(|mapping| mapping.phrases.iter().cloned())
+            .collect()
+    }
+
+    /// Action for a transcription in the given language
+    /// Falls back to the English mappings for languages without their own
+    pub fn match_command(&self, text: &str, language: Option<&str>) -> Option<String> {
+        let text = text.to_lowercase();
+        let mappings = language
+            .and_then(|language| self.language(language))
+            .or_else(|| self.language(FALLBACK_LANGUAGE))?;
+
+        mappings
+            .iter()
+            .find(|mapping| {
+                mapping
+                    .phrases
+                    .iter()
+                    .any(|phrase| text.contains(&phrase.to_lowercase()))
+            })
+            .map(|mapping| mapping.action.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_per_language() {
+        let map = CommandMap::default();
+
+        assert_eq!(map.match_command("Hello there", Some("en")), Some("greeting".to_string()));
+        assert_eq!(map.match_command("Bitte Hilfe!", Some("de")), Some("show_help".to_string()));
+        assert_eq!(map.match_command("作業を開始してください", Some("ja")), Some("start_workflow".to_string()));
+
+        // German phrases don't trigger from English mappings
+        assert_eq!(map.match_command("Bericht", Some("en")), None);
+    }
+
+    #[test]
+    fn test_fallback_and_overrides() {
+        let mut map = CommandMap::default();
+
+        // No French mappings, so English ones apply
+        assert_eq!(map.match_command("status", Some("fr")), Some("status_check".to_string()));
+        assert_eq!(map.match_command("status", None), Some("status_check".to_string()));
+
+        map.set_language("FR", vec![CommandMapping::new("show_help", &["Aide"])]);
+        assert_eq!(map.match_command("à l'aide", Some("fr")), Some("show_help".to_string()));
+        assert_eq!(map.match_command("status", Some("fr")), None);
+
+        map.set_language("fr", Vec::new());
+        assert!(map.language("fr").is_none());
+    }
+}
@@ -0,0 +1,154 @@
+//! Audio file decoding module
+//! Loads WAV, FLAC, OGG Vorbis and MP3 files into the same shape the recorder produces
+
+use crate::audio::{AudioFormat, RecordedAudio};
+use crate::downmix::{downmix, DownmixMode};
+use crate::resample::{resample, WHISPER_SAMPLE_RATE};
+use anyhow::{Context, Result};
+use cpal::SampleFormat;
+use std::path::Path;
+use symphonia::core::audio::{AudioBufferRef, SampleBuffer};
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Decode the default audio track of a file into interleaved f32 samples
+pub fn decode_file(path: &Path) -> Result<RecordedAudio> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open audio file {}", path.display()))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .context("Unsupported or unrecognised audio file")?;
+    let mut reader = probed.format;
+
+    let track = reader.default_track().context("Audio file has no audio track")?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&params, &DecoderOptions::default())
+        .context("Unsupported audio codec")?;
+
+    let mut samples = Vec::new();
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    let mut spec = None;
+    let mut sample_format = SampleFormat::F32;
+
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            // End of stream is reported as an unexpected EOF
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e).context("Failed to read audio packet"),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Skip corrupt frames rather than failing the whole file
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e).context("Failed to decode audio"),
+        };
+
+        sample_format = native_format(&decoded);
+        let decoded_spec = *decoded.spec();
+        let needed = decoded.capacity() * decoded_spec.channels.count();
+        if !matches!(&buffer, Some(b) if b.capacity() >= needed) {
+            buffer = Some(SampleBuffer::new(decoded.capacity() as u64, decoded_spec));
+        }
+
+        let buffer = buffer.as_mut().expect("sample buffer allocated above");
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buffer.samples());
+        spec = Some(decoded_spec);
+    }
+
+    let spec = spec.context("Audio file contains no decodable audio")?;
+
+    let format = AudioFormat {
+        sample_rate: spec.rate,
+        channels: spec.channels.count() as u16,
+        sample_format,
+    };
+
+    Ok(RecordedAudio { samples, format })
+}
+
+/// Decode a file and convert it to the 16 kHz mono Whisper expects
+pub fn load_for_whisper(path: &Path) -> Result<Vec<f32>> {
+    let audio = decode_file(path)?;
+    let mono = downmix(&audio.samples, audio.format.channels, DownmixMode::Average);
+    Ok(resample(&mono, audio.format.sample_rate, WHISPER_SAMPLE_RATE))
+}
+
+/// The format the codec decoded to, which is the file's own sample format for PCM
+/// Not every container reports this in its codec parameters
+fn native_format(buffer: &AudioBufferRef) -> SampleFormat {
+    match buffer {
+        AudioBufferRef::U8(_) => SampleFormat::U8,
+        AudioBufferRef::U16(_) => SampleFormat::U16,
+        AudioBufferRef::U24(_) | AudioBufferRef::U32(_) => SampleFormat::U32,
+        AudioBufferRef::S8(_) => SampleFormat::I8,
+        AudioBufferRef::S16(_) => SampleFormat::I16,
+        AudioBufferRef::S24(_) | AudioBufferRef::S32(_) => SampleFormat::I32,
+        AudioBufferRef::F32(_) => SampleFormat::F32,
+        AudioBufferRef::F64(_) => SampleFormat::F64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_wav() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stereo.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..48000 {
+            writer.write_sample(i16::MAX / 2).unwrap();
+            writer.write_sample(-(i16::MAX / 2)).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let audio = decode_file(&path).unwrap();
+        assert_eq!(audio.format.sample_rate, 48000);
+        assert_eq!(audio.format.channels, 2);
+        assert_eq!(audio.format.sample_format, SampleFormat::I16);
+        assert_eq!(audio.samples.len(), 96000);
+        assert!((audio.samples[0] - 0.5).abs() < 1e-3);
+        assert!((audio.samples[1] + 0.5).abs() < 1e-3);
+
+        // Opposite channels cancel out in the mono mix
+        let mono = load_for_whisper(&path).unwrap();
+        assert_eq!(mono.len(), 16000);
+        assert!(mono.iter().all(|s| s.abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_rejects_non_audio() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "definitely not audio").unwrap();
+        assert!(decode_file(&path).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+//! Multichannel downmix module
+//! Folds interleaved multichannel capture into the mono signal Whisper expects
+
+use serde::{Deserialize, Serialize};
+
+/// How interleaved channels are folded into a single mono channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum DownmixMode {
+    /// Average all channels sample by sample
+    #[default]
+    Average,
+    /// Keep a single channel (zero-based), clamped to the last available channel
+    Channel { index: u16 },
+    /// Keep whichever channel carries the most energy over the buffer
+    MaxEnergy,
+}
+
+/// Downmix interleaved samples to mono
+/// A trailing partial frame, if any, is dropped
+pub fn downmix(samples: &[f32], channels: u16, mode: DownmixMode) -> Vec<f32> {
+    let channels = channels.max(1) as usize;
+    if channels == 1 {
+        return samples.to_vec();
+    }
+
+    let frames = samples.chunks_exact(channels);
+
+    match mode {
+        DownmixMode::Average => frames
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect(),
+        DownmixMode::Channel { index } => {
+            let index = (index as usize).min(channels - 1);
+            frames.map(|frame| frame[index]).collect()
+        }
+        DownmixMode::MaxEnergy => {
+            let index = loudest_channel(samples, channels);
+            frames.map(|frame| frame[index]).collect()
+        }
+    }
+}
+
+/// Index of the channel with the highest sum of squares
+fn loudest_channel(samples: &[f32], channels: usize) -> usize {
+    let mut energy = vec![0.0f32; channels];
+    for frame in samples.chunks_exact(channels) {
+        for (total, &sample) in energy.iter_mut().zip(frame) {
+            *total += sample * sample;
+        }
+    }
+
+    energy
+        .iter()
+        .enumerate()
+        .fold((0, f32::MIN), |best, (i, &e)| if e > best.1 { (i, e) } else { best })
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mono_passthrough() {
+        let samples = vec![0.1, 0.2, 0.3];
+        assert_eq!(downmix(&samples, 1, DownmixMode::MaxEnergy), samples);
+    }
+
+    #[test]
+    fn test_stereo_downmix() {
+        // Left carries a quiet ramp, right a louder constant
+        let stereo = vec![0.1, 0.5, 0.2, 0.5, 0.3, 0.5];
+
+        let average = downmix(&stereo, 2, DownmixMode::Average);
+        let expected = [0.3, 0.35, 0.4];
+        assert_eq!(average.len(), 3);
+        for (got, want) in average.iter().zip(expected) {
+            assert!((got - want).abs() < 1e-6);
+        }
+
+        assert_eq!(downmix(&stereo, 2, DownmixMode::Channel { index: 0 }), vec![0.1, 0.2, 0.3]);
+        assert_eq!(downmix(&stereo, 2, DownmixMode::MaxEnergy), vec![0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_four_channel_downmix() {
+        // Only channel 2 has signal
+        let mut quad = Vec::new();
+        for i in 0..4 {
+            quad.extend_from_slice(&[0.0, 0.0, i as f32 * 0.4, 0.0]);
+        }
+
+        assert_eq!(downmix(&quad, 4, DownmixMode::Average), vec![0.0, 0.1, 0.2, 0.3]);
+        assert_eq!(downmix(&quad, 4, DownmixMode::Channel { index: 2 }), vec![0.0, 0.4, 0.8, 1.2]);
+        assert_eq!(downmix(&quad, 4, DownmixMode::MaxEnergy), vec![0.0, 0.4, 0.8, 1.2]);
+
+        // Out-of-range channel clamps to the last one
+        assert_eq!(downmix(&quad, 4, DownmixMode::Channel { index: 9 }), vec![0.0; 4]);
+    }
+
+    #[test]
+    fn test_partial_frame_dropped() {
+        let stereo = vec![0.2, 0.4, 0.6];
+        assert_eq!(downmix(&stereo, 2, DownmixMode::Channel { index: 1 }), vec![0.4]);
+    }
+}
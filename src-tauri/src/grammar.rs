@@ -0,0 +1,461 @@
+//! Command grammar module
+//! Restricts Whisper's output to an optional wake word followed by one registered command phrase
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Subtracted from the logit of every token the grammar doesn't allow, matching whisper.cpp's grammar penalty
+const GRAMMAR_PENALTY: f32 = 100.0;
+
+/// Separators Whisper writes between a wake word and the command
+const WAKE_SEPARATORS: [&str; 3] = [" ", ", ", "、"];
+
+/// Punctuation Whisper may close an utterance with, full-width for Japanese and Chinese
+const ENDINGS: [&str; 7] = ["", ".", "!", "?", "。", "！", "？"];
+
+/// The utterances accepted in command mode
+///
+/// Matching ignores case and one leading space, which Whisper puts before the first word.
+#[derive(Debug, Clone)]
+pub struct CommandGrammar {
+    wake_words: Vec<String>,
+    commands: Vec<String>,
+    /// Every complete utterance, lowercased and sorted for prefix searches
+    sentences: Vec<Vec<u8>>,
+}
+
+impl CommandGrammar {
+    pub fn new<W, C>(wake_words: W, commands: C) -> Self
+    where
+        W: IntoIterator<Item = String>,
+        C: IntoIterator<Item = String>,
+    {
+        let wake_words = normalize_phrases(wake_words);
+        let commands = normalize_phrases(commands);
+
+        let prefixes = std::iter::once(String::new()).chain(wake_words.iter().flat_map(|wake| {
+            WAKE_SEPARATORS
+                .iter()
+                .map(move |separator| format!("{}{}", wake, separator))
+        }));
+
+        let mut sentences: Vec<Vec<u8>> = prefixes
+            .flat_map(|prefix| {
+                commands.iter().flat_map(move |command| {
+                    let prefix = prefix.clone();
+                    ENDINGS
+                        .iter()
+                        .map(move |ending| format!("{}{}{}", prefix, command, ending).into_bytes())
+                })
+            })
+            .collect();
+        sentences.sort();
+        sentences.dedup();
+
+        Self {
+            wake_words,
+            commands,
+            sentences,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Whether `text` is a whole accepted utterance
+    pub fn is_complete(&self, text: &[u8]) -> bool {
+        let text = normalize(text);
+        self.sentences
+            .binary_search_by(|sentence| sentence.as_slice().cmp(text.as_slice()))
+            .is_ok()
+    }
+
+    /// Whether `text` can still be extended into an accepted utterance
+    pub fn allows_prefix(&self, text: &[u8]) -> bool {
+        self.completions(&normalize(text)).next().is_some()
+    }
+
+    /// The accepted utterances starting with the already normalized `prefix`, in sorted order
+    fn completions<'a>(&'a self, prefix: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        // Everything starting with `prefix` sorts at or just after it
+        let start = self.sentences.partition_point(|sentence| sentence.as_slice() < prefix);
+        self.sentences[start..]
+            .iter()
+            .map(Vec::as_slice)
+            .take_while(move |sentence| sentence.starts_with(prefix))
+    }
+
+    /// The grammar in whisper.cpp's GBNF notation
+    pub fn to_gbnf(&self) -> String {
+        let mut gbnf = String::new();
+        if self.wake_words.is_empty() {
+            gbnf.push_str("root ::= \" \"? command end?\n");
+        } else {
+            gbnf.push_str("root ::= \" \"? (wake (\",\"? \" \" | \"、\"))? command end?\n");
+            let _ = writeln!(gbnf, "wake ::= {}", alternatives(&self.wake_words));
+        }
+        let _ = writeln!(gbnf, "command ::= {}", alternatives(&self.commands));
+        gbnf.push_str("end ::= [.!?。！？]\n");
+        gbnf
+    }
+}
+
+/// A model's text tokens, indexed for looking them up by their bytes
+///
+/// Reading and sorting the vocabulary costs far more than decoding a short command, so it's
+/// built once per loaded model and shared by every constrained decode.
+pub struct Vocabulary {
+    /// Lowercased bytes of every text token, indexed by token id
+    tokens: Vec<Vec<u8>>,
+    /// Text token ids sorted by their lowercased bytes
+    by_text: Vec<u32>,
+    /// Text token ids sorted by their lowercased bytes without a leading space, which is how
+    /// the first token of an utterance is matched
+    by_stripped_text: Vec<u32>,
+    /// Id of the end-of-text token; text tokens sort before it, timestamps and other specials after
+    eot: usize,
+    /// Size of the whole vocabulary, special tokens included
+    n_vocab: usize,
+}
+
+impl Vocabulary {
+    /// `tokens` holds the text tokens, so its length is the end-of-text token id
+    pub fn new(tokens: Vec<Vec<u8>>, n_vocab: usize) -> Self {
+        let eot = tokens.len();
+        let tokens: Vec<Vec<u8>> = tokens.into_iter().map(|token| lowercase(&token)).collect();
+
+        let mut by_text: Vec<u32> = (0..eot as u32).collect();
+        by_text.sort_by(|&a, &b| tokens[a as usize].cmp(&tokens[b as usize]));
+        let mut by_stripped_text = by_text.clone();
+        by_stripped_text.sort_by(|&a, &b| {
+            strip_leading_space(&tokens[a as usize]).cmp(strip_leading_space(&tokens[b as usize]))
+        });
+
+        Self {
+            tokens,
+            by_text,
+            by_stripped_text,
+            eot,
+            n_vocab,
+        }
+    }
+
+    /// Ids in `index` of the tokens whose bytes are exactly `key`
+    fn matching<'a>(&self, index: &'a [u32], stripped: bool, key: &[u8]) -> &'a [u32] {
+        let token = |id: u32| {
+            let token = self.tokens[id as usize].as_slice();
+            if stripped {
+                strip_leading_space(token)
+            } else {
+                token
+            }
+        };
+        let start = index.partition_point(|&id| token(id) < key);
+        let len = index[start..].partition_point(|&id| token(id) == key);
+        &index[start..start + len]
+    }
+}
+
+/// Penalizes tokens that would take the decoded text outside a grammar
+///
+/// Rather than trying every token at each step, the tokens allowed next are looked up from the
+/// utterances still reachable, so a step costs about as much as the grammar is big.
+pub struct TokenConstraint {
+    grammar: CommandGrammar,
+    vocabulary: Arc<Vocabulary>,
+}
+
+impl TokenConstraint {
+    pub fn new(grammar: CommandGrammar, vocabulary: Arc<Vocabulary>) -> Self {
+        Self { grammar, vocabulary }
+    }
+
+    /// Number of logits per decoding step
+    pub fn n_vocab(&self) -> usize {
+        self.vocabulary.n_vocab
+    }
+
+    /// Adjust the logits for the next token given the tokens generated so far in this segment
+    /// Special tokens other than end-of-text are left alone so timestamps keep working
+    pub fn apply(&self, generated: &[i32], logits: &mut [f32]) {
+        let text: Vec<u8> = generated
+            .iter()
+            .filter_map(|&id| self.vocabulary.tokens.get(usize::try_from(id).ok()?))
+            .flatten()
+            .copied()
+            .collect();
+        let allowed = self.allowed_tokens(&text);
+        for (allowed, logit) in allowed.iter().zip(logits.iter_mut()) {
+            if !allowed && *logit != f32::NEG_INFINITY {
+                *logit -= GRAMMAR_PENALTY;
+            }
+        }
+
+        if !self.grammar.is_complete(&text) {
+            if let Some(logit) = logits.get_mut(self.vocabulary.eot) {
+                *logit -= GRAMMAR_PENALTY;
+            }
+        }
+    }
+
+    /// Which text tokens, by id, keep `text` on its way to an accepted utterance
+    fn allowed_tokens(&self, text: &[u8]) -> Vec<bool> {
+        let vocabulary = self.vocabulary.as_ref();
+        let mut allowed = vec![false; vocabulary.eot];
+
+        // Nothing decoded yet, so the token's own leading space is the one normalizing drops
+        let (prefix, index, stripped) = if text.is_empty() {
+            (Vec::new(), &vocabulary.by_stripped_text, true)
+        } else {
+            (normalize(text), &vocabulary.by_text, false)
+        };
+
+        // A token is allowed when it's a prefix of what some reachable utterance has left.
+        // The utterances are sorted, so prefixes shared with the previous one were already seen
+        let mut previous: Option<&[u8]> = None;
+        for sentence in self.grammar.completions(&prefix) {
+            let rest = &sentence[prefix.len()..];
+            let first_new = match previous {
+                Some(previous) => common_prefix_len(previous, rest) + 1,
+                None => 0,
+            };
+            for len in first_new..=rest.len() {
+                for &id in vocabulary.matching(index, stripped, &rest[..len]) {
+                    allowed[id as usize] = true;
+                }
+            }
+            previous = Some(rest);
+        }
+
+        allowed
+    }
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn normalize_phrases(phrases: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut normalized: Vec<String> = phrases
+        .into_iter()
+        .map(|phrase| phrase.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase())
+        .filter(|phrase| !phrase.is_empty())
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    normalized
+}
+
+/// Lowercase and drop the single leading space Whisper puts before the first word
+fn normalize(text: &[u8]) -> Vec<u8> {
+    lowercase(strip_leading_space(text))
+}
+
+/// `text` without its first character when that is whitespace, ASCII or Unicode
+fn strip_leading_space(text: &[u8]) -> &[u8] {
+    let len = match text.first() {
+        Some(&byte) if byte < 0x80 => 1,
+        Some(&byte) if byte >= 0xf0 => 4,
+        Some(&byte) if byte >= 0xe0 => 3,
+        Some(_) => 2,
+        None => return text,
+    };
+    let first = text
+        .get(..len)
+        .and_then(|first| std::str::from_utf8(first).ok())
+        .and_then(|first| first.chars().next());
+    match first {
+        Some(c) if c.is_whitespace() => &text[len..],
+        _ => text,
+    }
+}
+
+/// Lowercase UTF-8 text, letters like "Ö" included
+/// Tokens can end partway through a character, so bytes that aren't valid UTF-8 are kept as they are
+fn lowercase(text: &[u8]) -> Vec<u8> {
+    let mut lowered = Vec::with_capacity(text.len());
+    let mut rest = text;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                lowered.extend(valid.to_lowercase().bytes());
+                return lowered;
+            }
+            Err(error) => {
+                let (valid, invalid) = rest.split_at(error.valid_up_to());
+                let valid = std::str::from_utf8(valid).unwrap_or_default();
+                lowered.extend(valid.to_lowercase().bytes());
+                let invalid_len = error.error_len().unwrap_or(invalid.len());
+                lowered.extend(&invalid[..invalid_len]);
+                rest = &invalid[invalid_len..];
+            }
+        }
+    }
+}
+
+/// GBNF alternatives matching each phrase with its letters in either case
+fn alternatives(phrases: &[String]) -> String {
+    phrases
+        .iter()
+        .map(|phrase| case_insensitive(phrase))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+fn case_insensitive(phrase: &str) -> String {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    for c in phrase.chars() {
+        // Only letters whose cases are single characters; "ß" uppercases to "SS"
+        let (mut lower, mut upper) = (c.to_lowercase(), c.to_uppercase());
+        let cases = match (lower.next(), lower.next(), upper.next(), upper.next()) {
+            (Some(lower), None, Some(upper), None) if lower != upper => Some((lower, upper)),
+            _ => None,
+        };
+
+        if let Some((lower, upper)) = cases {
+            if !literal.is_empty() {
+                parts.push(format!("\"{}\"", literal));
+                literal.clear();
+            }
+            parts.push(format!("[{}{}]", lower, upper));
+        } else {
+            if c == '"' || c == '\\' {
+                literal.push('\\');
+            }
+            literal.push(c);
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(format!("\"{}\"", literal));
+    }
+    format!("({})", parts.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grammar() -> CommandGrammar {
+        CommandGrammar::new(
+            vec!["Kiku".to_string()],
+            vec!["start".to_string(), "status".to_string(), " start ".to_string()],
+        )
+    }
+
+    #[test]
+    fn test_accepted_utterances() {
+        let grammar = grammar();
+
+        assert!(grammar.is_complete(b" Start."));
+        assert!(grammar.is_complete(b"kiku, status"));
+        assert!(!grammar.is_complete(b" Sta"));
+        assert!(!grammar.is_complete(b" Stark."));
+
+        assert!(grammar.allows_prefix(b" Sta"));
+        assert!(grammar.allows_prefix(b" Kiku st"));
+        assert!(!grammar.allows_prefix(b" Stark"));
+        assert!(!grammar.allows_prefix(b" start now"));
+    }
+
+    #[test]
+    fn test_token_constraint() {
+        let vocab = [" start", " stark", " st", "art", ".", " kiku", ",", " hello"]
+            .iter()
+            .map(|t| t.as_bytes().to_vec())
+            .collect();
+        let constraint = TokenConstraint::new(grammar(), Arc::new(Vocabulary::new(vocab, 10)));
+        let eot = 8;
+
+        let mut logits = vec![0.0; 10];
+        constraint.apply(&[], &mut logits);
+        let allowed: Vec<bool> = logits.iter().map(|&l| l == 0.0).collect();
+        // " start", " st" and " kiku" can begin an utterance; timestamps (index 9) are untouched
+        assert_eq!(
+            allowed,
+            vec![true, false, true, false, false, true, false, false, false, true]
+        );
+
+        // After " st" + "art" the utterance may end or take a full stop
+        let mut logits = vec![0.0; 10];
+        constraint.apply(&[2, 3], &mut logits);
+        assert_eq!(logits[eot], 0.0);
+        assert_eq!(logits[4], 0.0);
+        assert_eq!(logits[0], -GRAMMAR_PENALTY);
+    }
+
+    #[test]
+    fn test_allowed_tokens_match_prefix_search() {
+        let grammar = grammar();
+        let vocab: Vec<Vec<u8>> = [
+            " start", " stark", " st", "art", ".", " kiku", ",", " hello", "s", " s", "t", "a",
+            "tus", " ", "", "!", " status", "ta", "rt.", "Start",
+        ]
+        .iter()
+        .map(|t| t.as_bytes().to_vec())
+        .collect();
+        let constraint = TokenConstraint::new(
+            grammar.clone(),
+            Arc::new(Vocabulary::new(vocab.clone(), vocab.len())),
+        );
+
+        for generated in [&[][..], &[2], &[9, 17], &[5, 6], &[5, 6, 13], &[13], &[2, 3, 4]] {
+            let text: Vec<u8> = generated.iter().flat_map(|&id| vocab[id as usize].clone()).collect();
+            let allowed = constraint.allowed_tokens(&text);
+            for (id, token) in vocab.iter().enumerate() {
+                let extended = [text.as_slice(), token].concat();
+                assert_eq!(
+                    allowed[id],
+                    grammar.allows_prefix(&extended),
+                    "{:?} after {:?}",
+                    String::from_utf8_lossy(token),
+                    String::from_utf8_lossy(&text)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_ascii_phrases() {
+        let grammar = CommandGrammar::new(
+            vec!["Kiku".to_string()],
+            vec!["Öffnen".to_string(), "開始".to_string()],
+        );
+
+        assert!(grammar.is_complete(" ÖFFNEN!".as_bytes()));
+        assert!(grammar.is_complete("Kiku、開始。".as_bytes()));
+        assert!(grammar.is_complete("\u{3000}開始？".as_bytes()));
+        assert!(grammar.allows_prefix(" Öff".as_bytes()));
+        assert!(!grammar.allows_prefix(" Off".as_bytes()));
+
+        // "開" split across two byte-level tokens, as Whisper's vocabulary does
+        let vocab = vec![
+            b" \xe9\x96".to_vec(),
+            b"\x8b".to_vec(),
+            "始".into(),
+            "。".into(),
+            " Ö".into(),
+        ];
+        let constraint = TokenConstraint::new(grammar.clone(), Arc::new(Vocabulary::new(vocab, 5)));
+        let mut logits = vec![0.0; 5];
+        constraint.apply(&[0, 1, 2], &mut logits);
+        let penalty = -GRAMMAR_PENALTY;
+        assert_eq!(logits, vec![penalty, penalty, penalty, 0.0, penalty]);
+
+        let gbnf = grammar.to_gbnf();
+        assert!(gbnf.contains("command ::= ([öÖ] [fF] [fF] [nN] [eE] [nN]) | (\"開始\")\n"), "{}", gbnf);
+    }
+
+    #[test]
+    fn test_gbnf() {
+        let gbnf = grammar().to_gbnf();
+        assert!(
+            gbnf.starts_with("root ::= \" \"? (wake (\",\"? \" \" | \"、\"))? command end?\n")
+        );
+        assert!(gbnf.contains("wake ::= ([kK] [iI] [kK] [uU])\n"));
+        assert!(gbnf.contains(
+            "command ::= ([sS] [tT] [aA] [rR] [tT]) | ([sS] [tT] [aA] [tT] [uU] [sS])\n"
+        ));
+    }
+}
@@ -1,19 +1,44 @@
 mod audio;
+mod audio_source;
+mod command_map;
+mod decode;
+mod downmix;
+mod grammar;
+mod model_catalog;
+mod model_download;
+mod model_info;
+mod recognizer;
+mod resample;
+mod ring_buffer;
+mod streaming;
 mod vad;
 mod voice_commands;
 mod whisper;
+mod worker;
 
 use audio::AudioDeviceInfo;
 use audio::AudioRecorder;
+use command_map::CommandMapping;
+use downmix::DownmixMode;
+use model_catalog::{InstalledModel, ModelList};
+use model_download::ModelDownloader;
+use model_info::ModelInfo;
 use parking_lot::Mutex;
+use recognizer::HttpRecognizer;
 use std::path::PathBuf;
 use std::sync::Arc;
+use streaming::PartialTranscript;
+use tauri::Emitter;
 use tauri::State;
 use tauri::Manager;
-use voice_commands::{RecordingStatus, VoiceCommand, VoiceCommandHandler};
+use tauri_plugin_store::StoreExt;
+use vad::ThresholdMode;
+use voice_commands::{ModelSwitchEvent, RecordingStatus, VoiceCommand, VoiceCommandHandler};
+use whisper::{Interrupted, Transcription, TranscriptionOptions};
 
 pub struct AppState {
     voice_handler: Arc<Mutex<Option<VoiceCommandHandler>>>,
+    model_downloader: ModelDownloader,
 }
 
 #[tauri::command]
@@ -25,13 +50,115 @@ fn initialize_voice(state: State<AppState>, model_path: String) -> Result<String
     }
 
     let handler = VoiceCommandHandler::new(path);
-    handler.initialize().map_err(|e| e.to_string())?;
+    // Include the cause, such as a truncated or non-Whisper model file
+    handler.initialize().map_err(|e| format!("{:#}", e))?;
 
     *state.voice_handler.lock() = Some(handler);
 
     Ok("Voice system initialized successfully".to_string())
 }
 
+/// Transcribe on a server speaking OpenAI's `/v1/audio/transcriptions` API instead of a local model
+#[tauri::command]
+fn initialize_remote_voice(
+    state: State<AppState>,
+    base_url: String,
+    api_key: Option<String>,
+    model: Option<String>,
+) -> Result<String, String> {
+    let recognizer = HttpRecognizer::new(&base_url, api_key, model);
+    let handler = VoiceCommandHandler::with_recognizer(Arc::new(recognizer));
+    handler.initialize().map_err(|e| format!("{:#}", e))?;
+
+    *state.voice_handler.lock() = Some(handler);
+
+    Ok(format!("Voice system using transcription server at {}", base_url.trim()))
+}
+
+fn emit_model_switch(app: &tauri::AppHandle, event: ModelSwitchEvent) {
+    if let Err(e) = app.emit("model_switch", event) {
+        eprintln!("Failed to emit model switch event: {}", e);
+    }
+}
+
+/// Swap the command model in place, keeping device selection and any listening session
+#[tauri::command]
+async fn switch_model(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    model_path: String,
+) -> Result<String, String> {
+    let path = PathBuf::from(&model_path);
+    if !path.exists() {
+        return Err(format!("Model file not found at: {}", path.display()));
+    }
+
+    // Clone the handler Arc to avoid holding the lock across await
+    let handler_arc = {
+        let handler_lock = state.voice_handler.lock();
+        handler_lock
+            .as_ref()
+            .ok_or("Voice system not initialized")?
+            .clone()
+    };
+
+    emit_model_switch(&app, ModelSwitchEvent::new("loading", &model_path, "Loading model..."));
+
+    // Loading a model takes seconds, keep it off the async runtime
+    let result = tokio::task::spawn_blocking(move || handler_arc.switch_model(path))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result.map_err(|e| format!("{:#}", e)));
+
+    match result {
+        Ok(()) => {
+            let message = format!("Switched to model: {}", model_path);
+            emit_model_switch(&app, ModelSwitchEvent::new("loaded", &model_path, message.clone()));
+            Ok(message)
+        }
+        Err(e) => {
+            emit_model_switch(&app, ModelSwitchEvent::new("failed", &model_path, e.clone()));
+            Err(e)
+        }
+    }
+}
+
+#[tauri::command]
+fn set_wake_model(state: State<AppState>, model_path: Option<String>) -> Result<String, String> {
+    let handler_lock = state.voice_handler.lock();
+    let handler = handler_lock
+        .as_ref()
+        .ok_or("Voice system not initialized")?;
+
+    let path = model_path.map(PathBuf::from);
+    if let Some(path) = &path {
+        if !path.exists() {
+            return Err(format!("Model file not found at: {}", path.display()));
+        }
+    }
+
+    let loaded = path.is_some();
+    handler.set_wake_model(path).map_err(|e| format!("{:#}", e))?;
+
+    Ok(if loaded {
+        "Wake word model loaded".to_string()
+    } else {
+        "Wake word detection uses the command model".to_string()
+    })
+}
+
+#[tauri::command]
+fn get_wake_model(state: State<AppState>) -> Result<Option<String>, String> {
+    let handler_lock = state.voice_handler.lock();
+    let handler = handler_lock
+        .as_ref()
+        .ok_or("Voice system not initialized")?;
+
+    Ok(handler
+        .wake_model_path()
+        .map(|path| path.to_string_lossy().to_string()))
+}
+
 #[tauri::command]
 fn start_recording(state: State<AppState>) -> Result<String, String> {
     let handler_lock = state.voice_handler.lock();
@@ -44,6 +171,15 @@ fn start_recording(state: State<AppState>) -> Result<String, String> {
     Ok("Recording started".to_string())
 }
 
+/// Message for a failed transcription; an interrupted one reports just
+/// "Transcription cancelled" or "Transcription timed out" so the frontend can tell them apart
+fn transcription_error(e: anyhow::Error) -> String {
+    match e.downcast_ref::<Interrupted>() {
+        Some(interrupted) => interrupted.to_string(),
+        None => e.to_string(),
+    }
+}
+
 #[tauri::command]
 async fn stop_recording(state: State<'_, AppState>) -> Result<VoiceCommand, String> {
     // Clone the handler Arc to avoid holding the lock across await
@@ -58,11 +194,23 @@ async fn stop_recording(state: State<'_, AppState>) -> Result<VoiceCommand, Stri
     let command = handler_arc
         .stop_recording_and_transcribe()
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(transcription_error)?;
 
     Ok(command)
 }
 
+/// Stop the command or file transcription in progress; its caller gets "Transcription cancelled"
+/// Returns whether anything was running
+#[tauri::command]
+fn cancel_transcription(state: State<AppState>) -> Result<bool, String> {
+    let handler_lock = state.voice_handler.lock();
+    let handler = handler_lock
+        .as_ref()
+        .ok_or("Voice system not initialized")?;
+
+    Ok(handler.cancel_transcription() > 0)
+}
+
 #[tauri::command]
 fn get_recording_status(state: State<AppState>) -> Result<RecordingStatus, String> {
     let handler_lock = state.voice_handler.lock();
@@ -94,13 +242,26 @@ fn is_voice_initialized(state: State<AppState>) -> bool {
 }
 
 #[tauri::command]
-fn start_background_listening(state: State<AppState>) -> Result<String, String> {
+async fn start_background_listening(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
     let handler_lock = state.voice_handler.lock();
     let handler = handler_lock
         .as_ref()
         .ok_or("Voice system not initialized")?;
 
-    handler.start_background_listening().map_err(|e| e.to_string())?;
+    let partial_app = app.clone();
+    handler
+        .start_background_listening(
+            move |event| {
+                if let Err(e) = app.emit("listening_event", event) {
+                    eprintln!("Failed to emit listening event: {}", e);
+                }
+            },
+            move |partial| emit_partial_transcript(&partial_app, partial),
+        )
+        .map_err(|e| e.to_string())?;
 
     Ok("Background listening started".to_string())
 }
@@ -127,8 +288,17 @@ fn is_background_listening(state: State<AppState>) -> bool {
     }
 }
 
+fn emit_partial_transcript(app: &tauri::AppHandle, partial: PartialTranscript) {
+    if let Err(e) = app.emit("partial_transcript", partial) {
+        eprintln!("Failed to emit partial transcript: {}", e);
+    }
+}
+
 #[tauri::command]
-async fn record_command_with_vad(state: State<'_, AppState>) -> Result<VoiceCommand, String> {
+async fn record_command_with_vad(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<VoiceCommand, String> {
     // Clone the handler Arc to avoid holding the lock across await
     let handler_arc = {
         let handler_lock = state.voice_handler.lock();
@@ -139,110 +309,235 @@ async fn record_command_with_vad(state: State<'_, AppState>) -> Result<VoiceComm
     };
 
     let command = handler_arc
-        .record_command_with_vad()
+        .record_command_with_vad(&|partial| emit_partial_transcript(&app, partial))
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(transcription_error)?;
 
     Ok(command)
 }
 
 #[tauri::command]
-async fn download_model(app: tauri::AppHandle, model_name: String) -> Result<String, String> {
-    use std::fs;
-    use std::io::Write;
+async fn transcribe_file(
+    state: State<'_, AppState>,
+    path: String,
+    options: Option<TranscriptionOptions>,
+) -> Result<VoiceCommand, String> {
+    let path = PathBuf::from(path);
 
-    // Get the app data directory
-    let app_data_dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+    if !path.exists() {
+        return Err(format!("Audio file not found at: {}", path.display()));
+    }
 
-    let models_dir = app_data_dir.join("models");
-    fs::create_dir_all(&models_dir).map_err(|e| format!("Failed to create models directory: {}", e))?;
+    // Clone the handler Arc to avoid holding the lock across await
+    let handler_arc = {
+        let handler_lock = state.voice_handler.lock();
+        handler_lock
+            .as_ref()
+            .ok_or("Voice system not initialized")?
+            .clone()
+    };
 
-    let model_path = models_dir.join(&model_name);
+    handler_arc
+        .transcribe_file(path, options)
+        .await
+        .map_err(transcription_error)
+}
+
+#[tauri::command]
+async fn transcribe_file_detailed(
+    state: State<'_, AppState>,
+    path: String,
+    options: Option<TranscriptionOptions>,
+) -> Result<Transcription, String> {
+    let path = PathBuf::from(path);
 
-    // Check if model already exists
-    if model_path.exists() {
-        return Ok(format!("Model already downloaded at: {}", model_path.display()));
+    if !path.exists() {
+        return Err(format!("Audio file not found at: {}", path.display()));
     }
 
-    // Download from Hugging Face
-    let url = format!(
-        "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/{}",
-        model_name
-    );
+    // Clone the handler Arc to avoid holding the lock across await
+    let handler_arc = {
+        let handler_lock = state.voice_handler.lock();
+        handler_lock
+            .as_ref()
+            .ok_or("Voice system not initialized")?
+            .clone()
+    };
 
-    let response = reqwest::get(&url)
+    handler_arc
+        .transcribe_file_detailed(path, options)
         .await
-        .map_err(|e| format!("Failed to download model: {}", e))?;
+        .map_err(transcription_error)
+}
 
-    if !response.status().is_success() {
-        return Err(format!("Failed to download model: HTTP {}", response.status()));
-    }
+/// Stream a model into the models directory, emitting `download_progress` events
+/// Resumes an earlier partial download of the same model
+#[tauri::command]
+async fn download_model(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    model_name: String,
+    sha256: Option<String>,
+) -> Result<String, String> {
+    let app_data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+    let models_dir = app_data_dir.join("models");
 
-    let bytes = response
-        .bytes()
+    // Verified against the caller's checksum, the catalogue's pin, or the one Hugging Face
+    // publishes; mirrors don't publish one
+    let sha256 = sha256.map(|sha256| sha256.trim().to_string()).filter(|sha256| !sha256.is_empty());
+    let model_path = state
+        .model_downloader
+        .download(&models_dir, &model_name, sha256.as_deref(), |progress| {
+            if let Err(e) = app.emit("download_progress", progress) {
+                eprintln!("Failed to emit download progress: {}", e);
+            }
+        })
         .await
-        .map_err(|e| format!("Failed to read response: {}", e))?;
+        .map_err(|e| format!("{:#}", e))?;
+
+    Ok(format!("Model downloaded successfully to: {}", model_path.display()))
+}
+
+#[tauri::command]
+fn cancel_download(state: State<AppState>, model_name: String) -> bool {
+    state.model_downloader.cancel(&model_name)
+}
 
-    let mut file = fs::File::create(&model_path)
-        .map_err(|e| format!("Failed to create model file: {}", e))?;
+/// Download models from a mirror; model file names are appended to `base_url`
+#[tauri::command]
+fn set_download_base_url(state: State<AppState>, base_url: Option<String>) -> Result<(), String> {
+    let base_url = base_url
+        .filter(|url| !url.trim().is_empty())
+        .unwrap_or_else(|| model_download::DEFAULT_BASE_URL.to_string());
+    if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+        return Err(format!("Not an HTTP URL: {}", base_url));
+    }
 
-    file.write_all(&bytes)
-        .map_err(|e| format!("Failed to write model file: {}", e))?;
+    state.model_downloader.set_base_url(base_url.trim());
+    Ok(())
+}
 
-    Ok(format!("Model downloaded successfully to: {}", model_path.display()))
+#[tauri::command]
+fn get_download_base_url(state: State<AppState>) -> String {
+    state.model_downloader.base_url()
 }
 
 #[tauri::command]
 async fn get_model_path(app: tauri::AppHandle, model_name: String) -> Result<String, String> {
-    let app_data_dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
-
-    let model_path = app_data_dir.join("models").join(&model_name);
+    let model_path =
+        model_catalog::model_path(&models_dir(&app)?, &model_name).map_err(|e| e.to_string())?;
     Ok(model_path.to_string_lossy().to_string())
 }
 
+/// Every downloaded model with what its header says about it
+#[tauri::command]
+async fn list_available_models(app: tauri::AppHandle) -> Result<Vec<InstalledModel>, String> {
+    let models_dir = models_dir(&app)?;
+    let default_model = default_model_path(&app);
+
+    tokio::task::spawn_blocking(move || {
+        model_catalog::installed_models(&models_dir, default_model.as_deref())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+/// Type, language support, quantization and size of a downloaded model, read from its header
 #[tauri::command]
-async fn list_available_models(app: tauri::AppHandle) -> Result<Vec<String>, String> {
-    use std::fs;
+async fn get_model_info(app: tauri::AppHandle, model_name: String) -> Result<ModelInfo, String> {
+    let model_path =
+        model_catalog::model_path(&models_dir(&app)?, &model_name).map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || model_info::inspect(&model_path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
 
+fn models_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
     let app_data_dir = app
         .path()
         .app_data_dir()
         .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+    Ok(app_data_dir.join("models"))
+}
 
-    let models_dir = app_data_dir.join("models");
+/// The model loaded on startup, as saved by the frontend
+fn default_model_path(app: &tauri::AppHandle) -> Option<PathBuf> {
+    let store = app.store("settings.json").ok()?;
+    let path = store.get("modelPath")?;
+    path.as_str().map(PathBuf::from)
+}
 
-    // Check if models directory exists
-    if !models_dir.exists() {
-        return Ok(Vec::new());
+/// Installed models, catalogue models not downloaded yet, and disk usage
+#[tauri::command]
+async fn list_models(app: tauri::AppHandle) -> Result<ModelList, String> {
+    let models_dir = models_dir(&app)?;
+    let default_model = default_model_path(&app);
+
+    let installed = tokio::task::spawn_blocking(move || {
+        model_catalog::installed_models(&models_dir, default_model.as_deref())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    Ok(ModelList::new(installed))
+}
+
+/// Delete a downloaded model, unless the voice system is using it
+#[tauri::command]
+async fn delete_model(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    model_name: String,
+) -> Result<String, String> {
+    let models_dir = models_dir(&app)?;
+    let model_path = model_catalog::model_path(&models_dir, &model_name).map_err(|e| e.to_string())?;
+
+    let in_use = state
+        .voice_handler
+        .lock()
+        .as_ref()
+        .is_some_and(|handler| handler.uses_model(&model_path));
+    if in_use {
+        return Err(format!("{} is in use, switch to another model first", model_name));
     }
 
-    // Read directory and collect .bin files
-    let mut models = Vec::new();
-    let entries = fs::read_dir(&models_dir)
-        .map_err(|e| format!("Failed to read models directory: {}", e))?;
-
-    for entry in entries {
-        if let Ok(entry) = entry {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(extension) = path.extension() {
-                    if extension == "bin" {
-                        if let Some(file_name) = path.file_name() {
-                            models.push(file_name.to_string_lossy().to_string());
-                        }
-                    }
-                }
-            }
+    model_catalog::delete_model(&models_dir, &model_name).map_err(|e| format!("{:#}", e))?;
+
+    // Don't leave startup pointing at a file that's gone
+    if default_model_path(&app).as_deref() == Some(model_path.as_path()) {
+        if let Ok(store) = app.store("settings.json") {
+            store.delete("modelPath");
+            let _ = store.save();
         }
     }
 
-    Ok(models)
+    Ok(format!("Deleted {}", model_name))
+}
+
+/// Make a downloaded model the one loaded on startup, returning its path
+#[tauri::command]
+async fn set_default_model(app: tauri::AppHandle, model_name: String) -> Result<String, String> {
+    let model_path =
+        model_catalog::model_path(&models_dir(&app)?, &model_name).map_err(|e| e.to_string())?;
+    if !model_path.is_file() {
+        return Err(format!("Model not installed: {}", model_name));
+    }
+
+    let model_path = model_path.to_string_lossy().to_string();
+    let store = app
+        .store("settings.json")
+        .map_err(|e| format!("Failed to open settings: {}", e))?;
+    store.set("modelPath", model_path.clone());
+    store
+        .save()
+        .map_err(|e| format!("Failed to save settings: {}", e))?;
+
+    Ok(model_path)
 }
 
 #[tauri::command]
@@ -266,13 +561,168 @@ fn list_audio_devices() -> Result<Vec<AudioDeviceInfo>, String> {
 fn set_audio_device(state: State<AppState>, device_name: Option<String>) -> Result<(), String> {
     let handler_lock = state.voice_handler.lock();
     if let Some(handler) = handler_lock.as_ref() {
-        handler.set_audio_device(device_name);
+        handler
+            .set_audio_device(device_name)
+            .map_err(|e| format!("Failed to switch audio device: {}", e))
+    } else {
+        Err("Voice system not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+fn set_downmix_mode(
+    state: State<AppState>,
+    device_name: Option<String>,
+    mode: DownmixMode,
+) -> Result<(), String> {
+    let handler_lock = state.voice_handler.lock();
+    if let Some(handler) = handler_lock.as_ref() {
+        handler
+            .set_downmix_mode(device_name, mode)
+            .map_err(|e| e.to_string())
+    } else {
+        Err("Voice system not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+fn set_pre_roll(state: State<AppState>, pre_roll_ms: u64) -> Result<(), String> {
+    let handler_lock = state.voice_handler.lock();
+    if let Some(handler) = handler_lock.as_ref() {
+        handler.set_pre_roll_ms(pre_roll_ms);
+        Ok(())
+    } else {
+        Err("Voice system not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+fn set_vad_mode(state: State<AppState>, mode: ThresholdMode) -> Result<(), String> {
+    let handler_lock = state.voice_handler.lock();
+    if let Some(handler) = handler_lock.as_ref() {
+        handler.set_vad_mode(mode);
+        Ok(())
+    } else {
+        Err("Voice system not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+fn set_transcription_options(
+    state: State<AppState>,
+    options: TranscriptionOptions,
+) -> Result<(), String> {
+    let handler_lock = state.voice_handler.lock();
+    if let Some(handler) = handler_lock.as_ref() {
+        handler.set_transcription_options(options);
+        Ok(())
+    } else {
+        Err("Voice system not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+fn set_min_confidence(state: State<AppState>, min_confidence: f32) -> Result<(), String> {
+    let handler_lock = state.voice_handler.lock();
+    if let Some(handler) = handler_lock.as_ref() {
+        handler.set_min_confidence(min_confidence);
+        Ok(())
+    } else {
+        Err("Voice system not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+fn set_command_mappings(
+    state: State<AppState>,
+    language: String,
+    mappings: Vec<CommandMapping>,
+) -> Result<(), String> {
+    let handler_lock = state.voice_handler.lock();
+    if let Some(handler) = handler_lock.as_ref() {
+        handler.set_command_mappings(&language, mappings);
         Ok(())
     } else {
         Err("Voice system not initialized".to_string())
     }
 }
 
+#[tauri::command]
+fn get_command_mappings(
+    state: State<AppState>,
+    language: String,
+) -> Result<Vec<CommandMapping>, String> {
+    let handler_lock = state.voice_handler.lock();
+    let handler = handler_lock
+        .as_ref()
+        .ok_or("Voice system not initialized")?;
+
+    Ok(handler.command_mappings(&language))
+}
+
+#[tauri::command]
+fn set_command_mode(state: State<AppState>, enabled: bool) -> Result<(), String> {
+    let handler_lock = state.voice_handler.lock();
+    if let Some(handler) = handler_lock.as_ref() {
+        handler.set_command_mode(enabled);
+        Ok(())
+    } else {
+        Err("Voice system not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+fn set_streaming(state: State<AppState>, enabled: bool) -> Result<(), String> {
+    let handler_lock = state.voice_handler.lock();
+    if let Some(handler) = handler_lock.as_ref() {
+        handler.set_streaming(enabled);
+        Ok(())
+    } else {
+        Err("Voice system not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+fn get_command_grammar(state: State<AppState>) -> Result<String, String> {
+    let handler_lock = state.voice_handler.lock();
+    let handler = handler_lock
+        .as_ref()
+        .ok_or("Voice system not initialized")?;
+
+    Ok(handler.command_grammar_gbnf())
+}
+
+#[tauri::command]
+fn set_custom_vocabulary(state: State<AppState>, vocabulary: Vec<String>) -> Result<(), String> {
+    let handler_lock = state.voice_handler.lock();
+    if let Some(handler) = handler_lock.as_ref() {
+        handler.set_custom_vocabulary(vocabulary);
+        Ok(())
+    } else {
+        Err("Voice system not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+fn get_custom_vocabulary(state: State<AppState>) -> Result<Vec<String>, String> {
+    let handler_lock = state.voice_handler.lock();
+    let handler = handler_lock
+        .as_ref()
+        .ok_or("Voice system not initialized")?;
+
+    Ok(handler.custom_vocabulary())
+}
+
+#[tauri::command]
+fn get_transcription_options(state: State<AppState>) -> Result<TranscriptionOptions, String> {
+    let handler_lock = state.voice_handler.lock();
+    let handler = handler_lock
+        .as_ref()
+        .ok_or("Voice system not initialized")?;
+
+    Ok(handler.transcription_options())
+}
+
 #[tauri::command]
 async fn log_voice_command(app: tauri::AppHandle, command: VoiceCommand) -> Result<(), String> {
     use std::fs::{self, OpenOptions};
@@ -302,10 +752,11 @@ async fn log_voice_command(app: tauri::AppHandle, command: VoiceCommand) -> Resu
         .format("%Y-%m-%d %H:%M:%S UTC");
 
     let log_entry = format!(
-        "[{}] {} (confidence: {})\n",
+        "[{}] {} (confidence: {:.2}, language: {})\n",
         datetime,
         command.text,
-        command.confidence
+        command.confidence,
+        command.language.as_deref().unwrap_or("unknown")
     );
 
     // Append to log file
@@ -343,9 +794,14 @@ pub fn run() {
         .plugin(tauri_plugin_store::Builder::default().build())
         .manage(AppState {
             voice_handler: Arc::new(Mutex::new(None)),
+            model_downloader: ModelDownloader::new(),
         })
         .invoke_handler(tauri::generate_handler![
             initialize_voice,
+            initialize_remote_voice,
+            switch_model,
+            set_wake_model,
+            get_wake_model,
             start_recording,
             stop_recording,
             get_recording_status,
@@ -355,12 +811,35 @@ pub fn run() {
             stop_background_listening,
             is_background_listening,
             record_command_with_vad,
+            cancel_transcription,
+            transcribe_file,
+            transcribe_file_detailed,
             download_model,
+            cancel_download,
+            set_download_base_url,
+            get_download_base_url,
             get_model_path,
             list_available_models,
+            list_models,
+            delete_model,
+            set_default_model,
+            get_model_info,
             get_models_directory,
             list_audio_devices,
             set_audio_device,
+            set_downmix_mode,
+            set_pre_roll,
+            set_vad_mode,
+            set_transcription_options,
+            get_transcription_options,
+            set_min_confidence,
+            set_command_mappings,
+            get_command_mappings,
+            set_command_mode,
+            get_command_grammar,
+            set_streaming,
+            set_custom_vocabulary,
+            get_custom_vocabulary,
             log_voice_command,
             get_log_file_path
         ])
@@ -0,0 +1,230 @@
+//! Model catalogue module
+//! The Whisper models kiku can download, and what is installed in the models directory
+
+use crate::model_info::{self, ModelInfo};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// File extensions of model files, GGML and GGUF
+const MODEL_EXTENSIONS: [&str; 2] = ["bin", "gguf"];
+
+/// A downloadable model
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CatalogEntry {
+    /// Name of the file on the download server and in the models directory
+    pub file_name: &'static str,
+    pub name: &'static str,
+    /// Approximate download size
+    pub size_mb: u32,
+    pub multilingual: bool,
+    /// Weight type, "f16" for the unquantized models
+    pub quantization: &'static str,
+    pub recommended_use: &'static str,
+    /// Pinned SHA-256, None to verify against the hash the download server publishes
+    pub sha256: Option<&'static str>,
+}
+
+const fn entry(
+    file_name: &'static str,
+    name: &'static str,
+    size_mb: u32,
+    multilingual: bool,
+    quantization: &'static str,
+    recommended_use: &'static str,
+    sha256: Option<&'static str>,
+) -> CatalogEntry {
+    CatalogEntry {
+        file_name,
+        name,
+        size_mb,
+        multilingual,
+        quantization,
+        recommended_use,
+        sha256,
+    }
+}
+
+/// whisper.cpp's published models, smallest first within each family
+///
+/// Each entry pins the SHA-256 of the file on Hugging Face (the LFS object id). An entry left
+/// at None is only downloaded when the server publishes a checksum, so fill pins in from
+/// `sha256sum` of a known-good download or Hugging Face's file page.
+pub const CATALOG: &[CatalogEntry] = &[
+    entry("ggml-tiny.en.bin", "Tiny (English)", 75, false, "f16", "Wake words on slow machines", None),
+    entry("ggml-tiny.en-q5_1.bin", "Tiny (English, Q5_1)", 31, false, "q5_1", "Wake words with the least memory", None),
+    entry("ggml-tiny.en-q8_0.bin", "Tiny (English, Q8_0)", 42, false, "q8_0", "Wake words with little memory", None),
+    entry("ggml-tiny.bin", "Tiny (Multilingual)", 75, true, "f16", "Wake words in any language", None),
+    entry("ggml-base.en.bin", "Base (English)", 142, false, "f16", "Best balance of speed and accuracy", None),
+    entry("ggml-base.en-q5_1.bin", "Base (English, Q5_1)", 57, false, "q5_1", "Commands on machines with little memory", None),
+    entry("ggml-base.en-q8_0.bin", "Base (English, Q8_0)", 78, false, "q8_0", "Commands with less memory", None),
+    entry("ggml-base.bin", "Base (Multilingual)", 142, true, "f16", "Commands in other languages", None),
+    entry("ggml-small.en.bin", "Small (English)", 466, false, "f16", "Accurate commands and dictation", None),
+    entry("ggml-small.en-q5_1.bin", "Small (English, Q5_1)", 181, false, "q5_1", "Accurate commands with less memory", None),
+    entry("ggml-small.en-q8_0.bin", "Small (English, Q8_0)", 252, false, "q8_0", "Accurate commands with less memory", None),
+    entry("ggml-small.bin", "Small (Multilingual)", 466, true, "f16", "Accurate multilingual commands", None),
+    entry("ggml-medium.en.bin", "Medium (English)", 1500, false, "f16", "File transcription on fast machines", None),
+    entry("ggml-medium.en-q5_0.bin", "Medium (English, Q5_0)", 514, false, "q5_0", "File transcription with less memory", None),
+    entry("ggml-medium.bin", "Medium (Multilingual)", 1500, true, "f16", "Multilingual file transcription", None),
+    entry("ggml-large-v3.bin", "Large v3", 2900, true, "f16", "Highest accuracy, needs a GPU-class machine", None),
+    entry("ggml-large-v3-q5_0.bin", "Large v3 (Q5_0)", 1080, true, "q5_0", "High accuracy with less memory", None),
+    entry("ggml-large-v3-turbo.bin", "Large v3 Turbo", 1500, true, "f16", "Near-large accuracy at medium speed", None),
+    entry("ggml-large-v3-turbo-q5_0.bin", "Large v3 Turbo (Q5_0)", 547, true, "q5_0", "Near-large accuracy with less memory", None),
+    entry("ggml-large-v3-turbo-q8_0.bin", "Large v3 Turbo (Q8_0)", 834, true, "q8_0", "Near-large accuracy with less memory", None),
+];
+
+pub fn find(file_name: &str) -> Option<&'static CatalogEntry> {
+    CATALOG.iter().find(|entry| entry.file_name == file_name)
+}
+
+/// A model file in the models directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstalledModel {
+    pub file_name: String,
+    pub size_bytes: u64,
+    /// Whether this is the model loaded on startup
+    pub is_default: bool,
+    /// None for models that didn't come from the catalogue
+    pub catalog: Option<CatalogEntry>,
+    /// What the header says, None when it couldn't be read
+    pub info: Option<ModelInfo>,
+    /// Why the header couldn't be read
+    pub info_error: Option<String>,
+}
+
+/// Every model file in `models_dir`, sorted by name; partial downloads are left out
+pub fn installed_models(models_dir: &Path, default_model: Option<&Path>) -> Result<Vec<InstalledModel>> {
+    if !models_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut models = Vec::new();
+    for entry in std::fs::read_dir(models_dir).context("Failed to read models directory")? {
+        let path = entry.context("Failed to read models directory")?.path();
+        if !path.is_file() || !is_model_file(&path) {
+            continue;
+        }
+        let Some(file_name) = path.file_name().map(|name| name.to_string_lossy().to_string()) else {
+            continue;
+        };
+
+        let (info, info_error) = match model_info::inspect(&path) {
+            Ok(info) => (Some(info), None),
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        models.push(InstalledModel {
+            size_bytes: path.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+            is_default: default_model == Some(path.as_path()),
+            catalog: find(&file_name).cloned(),
+            file_name,
+            info,
+            info_error,
+        });
+    }
+
+    models.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    Ok(models)
+}
+
+/// Installed models next to the catalogue models not downloaded yet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModelList {
+    pub installed: Vec<InstalledModel>,
+    pub available: Vec<CatalogEntry>,
+    /// Space taken by the installed models
+    pub disk_usage_bytes: u64,
+}
+
+impl ModelList {
+    pub fn new(installed: Vec<InstalledModel>) -> Self {
+        let available = CATALOG
+            .iter()
+            .filter(|entry| !installed.iter().any(|model| model.file_name == entry.file_name))
+            .cloned()
+            .collect();
+        let disk_usage_bytes = installed.iter().map(|model| model.size_bytes).sum();
+
+        Self {
+            installed,
+            available,
+            disk_usage_bytes,
+        }
+    }
+}
+
+pub fn is_model_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| MODEL_EXTENSIONS.contains(&extension))
+}
+
+/// Where `file_name` lives in `models_dir`, refusing anything but a plain model file name
+/// so names from the frontend can't reach outside the directory
+pub fn model_path(models_dir: &Path, file_name: &str) -> Result<PathBuf> {
+    let path = models_dir.join(file_name);
+    if file_name.contains(['/', '\\', ':']) || file_name.starts_with('.') || !is_model_file(&path) {
+        return Err(anyhow::anyhow!("Not a model file: {}", file_name));
+    }
+    Ok(path)
+}
+
+/// Delete a model and any partial download of it
+pub fn delete_model(models_dir: &Path, file_name: &str) -> Result<()> {
+    let path = model_path(models_dir, file_name)?;
+    let _ = std::fs::remove_file(models_dir.join(format!("{}.part", file_name)));
+    std::fs::remove_file(&path).with_context(|| format!("Failed to delete {}", file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_is_consistent() {
+        for (i, entry) in CATALOG.iter().enumerate() {
+            assert!(is_model_file(Path::new(entry.file_name)), "{}", entry.file_name);
+            assert!(
+                CATALOG[..i].iter().all(|other| other.file_name != entry.file_name),
+                "{} listed twice",
+                entry.file_name
+            );
+            assert_eq!(entry.multilingual, !entry.file_name.contains(".en"), "{}", entry.file_name);
+            if let Some(sha256) = entry.sha256 {
+                assert!(
+                    sha256.len() == 64 && sha256.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')),
+                    "{} pins a malformed SHA-256",
+                    entry.file_name
+                );
+            }
+        }
+        assert_eq!(find("ggml-base.en-q5_1.bin").unwrap().quantization, "q5_1");
+    }
+
+    #[test]
+    fn test_installed_models_and_delete() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        for name in ["ggml-base.en.bin", "custom.gguf", "ggml-small.bin.part", "notes.txt"] {
+            std::fs::write(dir.join(name), b"model").unwrap();
+        }
+
+        let installed = installed_models(dir, Some(&dir.join("custom.gguf"))).unwrap();
+        let names: Vec<_> = installed.iter().map(|m| m.file_name.as_str()).collect();
+        assert_eq!(names, vec!["custom.gguf", "ggml-base.en.bin"]);
+        assert!(installed[0].is_default && installed[0].catalog.is_none());
+        assert_eq!(installed[1].catalog.as_ref().unwrap().name, "Base (English)");
+        assert_eq!(installed[1].size_bytes, 5);
+
+        // Placeholder files aren't real models, so only why is known
+        assert!(installed[1].info.is_none());
+        assert!(installed[1].info_error.as_ref().unwrap().contains("Not a Whisper model"));
+
+        assert!(delete_model(dir, "notes.txt").is_err());
+        assert!(delete_model(dir, "../ggml-base.en.bin").is_err());
+        assert!(model_path(dir, "..\\ggml-base.en.bin").is_err());
+        assert!(model_path(dir, "").is_err());
+        assert_eq!(model_path(dir, "custom.gguf").unwrap(), dir.join("custom.gguf"));
+        delete_model(dir, "ggml-base.en.bin").unwrap();
+        assert!(!dir.join("ggml-base.en.bin").exists());
+    }
+}
@@ -0,0 +1,579 @@
+//! Model download module
+//! Streams models to a temporary file with progress, resume, checksum verification and cancellation
+
+use crate::{model_catalog, model_info};
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+
+/// Where whisper.cpp's GGML models are published
+pub const DEFAULT_BASE_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main";
+
+/// Suffix of a partly downloaded model, kept between attempts so downloads can resume
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// Least time between two progress events
+const PROGRESS_INTERVAL_MS: u64 = 100;
+
+/// Longest a download may go without receiving data before it fails
+const READ_TIMEOUT_SECS: u64 = 30;
+
+/// Longest to wait for the download server to accept a connection
+const CONNECT_TIMEOUT_SECS: u64 = 15;
+
+/// Payload of the `download_progress` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadProgress {
+    pub model_name: String,
+    /// Bytes on disk so far, including any resumed from an earlier attempt
+    pub downloaded: u64,
+    /// Full size of the model, when the server reports it
+    pub total: Option<u64>,
+}
+
+/// Downloads models into a directory, one download per model at a time
+pub struct ModelDownloader {
+    client: reqwest::Client,
+    /// Fetches only the first hop, where Hugging Face reports the file's SHA-256
+    metadata_client: reqwest::Client,
+    base_url: Mutex<String>,
+    /// Cancellation signals of the downloads in progress, by model name
+    active: Mutex<HashMap<String, Arc<Notify>>>,
+}
+
+impl ModelDownloader {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+                .read_timeout(Duration::from_secs(READ_TIMEOUT_SECS))
+                .build()
+                .expect("Failed to build HTTP client"),
+            metadata_client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+                .read_timeout(Duration::from_secs(READ_TIMEOUT_SECS))
+                .build()
+                .expect("Failed to build HTTP client"),
+            base_url: Mutex::new(DEFAULT_BASE_URL.to_string()),
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Download from a mirror instead of Hugging Face; model names are appended to this URL
+    pub fn set_base_url(&self, base_url: &str) {
+        *self.base_url.lock() = base_url.trim_end_matches('/').to_string();
+    }
+
+    pub fn base_url(&self) -> String {
+        self.base_url.lock().clone()
+    }
+
+    /// Ask a running download to stop, returning false if `model_name` isn't downloading
+    ///
+    /// The partial file is kept, so downloading the model again resumes where this one stopped.
+    pub fn cancel(&self, model_name: &str) -> bool {
+        match self.active.lock().get(model_name) {
+            Some(cancelled) => {
+                // Stores a permit, so the download stops even if it isn't waiting right now
+                cancelled.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Download `model_name` into `models_dir` and return its path
+    ///
+    /// The file is checked against `expected_sha256`, the catalogue's pin when that is None, or
+    /// else the SHA-256 the server publishes. It fails when none of them is known, and the file
+    /// only appears under its own name once complete and verified.
+    pub async fn download<F>(
+        &self,
+        models_dir: &Path,
+        model_name: &str,
+        expected_sha256: Option<&str>,
+        on_progress: F,
+    ) -> Result<PathBuf>
+    where
+        F: Fn(DownloadProgress),
+    {
+        let model_path = model_catalog::model_path(models_dir, model_name)?;
+        if model_path.exists() {
+            return Ok(model_path);
+        }
+
+        let cancelled = Arc::new(Notify::new());
+        {
+            let mut active = self.active.lock();
+            if active.contains_key(model_name) {
+                return Err(anyhow::anyhow!("{} is already downloading", model_name));
+            }
+            active.insert(model_name.to_string(), Arc::clone(&cancelled));
+        }
+
+        let result = self
+            .fetch(models_dir, model_name, expected_sha256, &cancelled, &on_progress)
+            .await;
+        self.active.lock().remove(model_name);
+
+        let partial_path = result?;
+        tokio::fs::rename(&partial_path, &model_path)
+            .await
+            .context("Failed to move downloaded model into place")?;
+        Ok(model_path)
+    }
+
+    /// Download into the partial file and verify it, returning the partial file's path
+    async fn fetch<F>(
+        &self,
+        models_dir: &Path,
+        model_name: &str,
+        expected_sha256: Option<&str>,
+        cancelled: &Notify,
+        on_progress: &F,
+    ) -> Result<PathBuf>
+    where
+        F: Fn(DownloadProgress),
+    {
+        tokio::fs::create_dir_all(models_dir)
+            .await
+            .context("Failed to create models directory")?;
+
+        let url = format!("{}/{}", self.base_url(), model_name);
+        if let Some(sha256) = expected_sha256 {
+            if !is_sha256(sha256) {
+                return Err(anyhow::anyhow!("Not a SHA-256 checksum: {}", sha256));
+            }
+        }
+        let pinned_sha256 = expected_sha256
+            .or_else(|| model_catalog::find(model_name).and_then(|entry| entry.sha256));
+        let expected_sha256 = match pinned_sha256 {
+            Some(sha256) => sha256.to_ascii_lowercase(),
+            None => self.published_sha256(&url).await.with_context(|| {
+                format!(
+                    "No checksum to verify {} against: it isn't pinned in the catalogue and the server didn't publish one",
+                    model_name
+                )
+            })?,
+        };
+
+        let partial_path = models_dir.join(format!("{}{}", model_name, PARTIAL_SUFFIX));
+        let resume_from = tokio::fs::metadata(&partial_path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+
+        let mut request = self.client.get(&url);
+        if resume_from > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", resume_from));
+        }
+        let mut response = tokio::select! {
+            response = request.send() => {
+                response.with_context(|| format!("Failed to download {}", model_name))?
+            }
+            _ = cancelled.notified() => {
+                return Err(anyhow::anyhow!("Download of {} cancelled", model_name));
+            }
+        };
+
+        let status = response.status();
+        let (mut downloaded, total) = if status == reqwest::StatusCode::PARTIAL_CONTENT {
+            (resume_from, content_range_total(&response))
+        } else if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE && resume_from > 0 {
+            // The previous attempt got everything; it only needs verifying
+            (resume_from, Some(resume_from))
+        } else if status.is_success() {
+            // No resume support, start over
+            (0, response.content_length())
+        } else {
+            return Err(anyhow::anyhow!(
+                "Failed to download {}: HTTP {}",
+                model_name,
+                status
+            ));
+        };
+
+        if status != reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(downloaded > 0)
+                .truncate(downloaded == 0)
+                .open(&partial_path)
+                .await
+                .context("Failed to create model file")?;
+
+            let progress_interval = std::time::Duration::from_millis(PROGRESS_INTERVAL_MS);
+            let mut last_progress = std::time::Instant::now();
+            on_progress(DownloadProgress {
+                model_name: model_name.to_string(),
+                downloaded,
+                total,
+            });
+
+            // Cancellation is raced against each chunk so a stalled server can't hold it up
+            loop {
+                let chunk = tokio::select! {
+                    chunk = response.chunk() => {
+                        chunk.with_context(|| format!("Failed to download {}", model_name))?
+                    }
+                    _ = cancelled.notified() => {
+                        file.flush().await.context("Failed to write model file")?;
+                        return Err(anyhow::anyhow!("Download of {} cancelled", model_name));
+                    }
+                };
+                let Some(chunk) = chunk else {
+                    break;
+                };
+
+                file.write_all(&chunk)
+                    .await
+                    .context("Failed to write model file")?;
+                downloaded += chunk.len() as u64;
+
+                if last_progress.elapsed() >= progress_interval {
+                    last_progress = std::time::Instant::now();
+                    on_progress(DownloadProgress {
+                        model_name: model_name.to_string(),
+                        downloaded,
+                        total,
+                    });
+                }
+            }
+
+            file.sync_all().await.context("Failed to write model file")?;
+        }
+
+        on_progress(DownloadProgress {
+            model_name: model_name.to_string(),
+            downloaded,
+            total,
+        });
+
+        if let Some(total) = total {
+            if downloaded != total {
+                return Err(anyhow::anyhow!(
+                    "Download of {} ended early: {} of {} bytes",
+                    model_name,
+                    downloaded,
+                    total
+                ));
+            }
+        }
+
+        let verify_path = partial_path.clone();
+        let verified = tokio::task::spawn_blocking(move || -> Result<()> {
+            let actual = sha256_file(&verify_path)?;
+            if actual != expected_sha256 {
+                // Corrupt rather than incomplete, so resuming from it would never succeed
+                let _ = std::fs::remove_file(&verify_path);
+                return Err(anyhow::anyhow!(
+                    "Checksum mismatch: expected {}, got {}",
+                    expected_sha256,
+                    actual
+                ));
+            }
+            model_info::inspect(&verify_path).map(|_| ())
+        })
+        .await
+        .context("Failed to spawn verification task")?;
+
+        verified.with_context(|| format!("Downloaded {} is not usable", model_name))?;
+        Ok(partial_path)
+    }
+
+    /// SHA-256 Hugging Face reports for files stored in Git LFS, read from the redirect
+    async fn published_sha256(&self, url: &str) -> Result<String> {
+        let response = self
+            .metadata_client
+            .head(url)
+            .send()
+            .await
+            .context("Failed to ask the server for the file's checksum")?;
+        let etag = response
+            .headers()
+            .get("x-linked-etag")
+            .and_then(|etag| etag.to_str().ok())
+            .context("Server didn't report a checksum")?;
+
+        let sha256 = etag.trim_start_matches("W/").trim_matches('"').to_ascii_lowercase();
+        if !is_sha256(&sha256) {
+            return Err(anyhow::anyhow!("Server reported a checksum that isn't SHA-256: {}", etag));
+        }
+        Ok(sha256)
+    }
+}
+
+impl Default for ModelDownloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Full size from a `Content-Range: bytes start-end/total` header
+fn content_range_total(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit('/')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Lowercase hex SHA-256 of a file
+fn is_sha256(text: &str) -> bool {
+    text.len() == 64 && text.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path).context("Failed to open file for hashing")?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let read = file.read(&mut buf).context("Failed to read file for hashing")?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    /// A GGML model with no mel filters or vocabulary and one 64 KiB f32 tensor,
+    /// enough for `model_info::inspect` and big enough to arrive in several chunks
+    fn model_bytes() -> Vec<u8> {
+        let mut bytes = 0x6767_6d6cu32.to_le_bytes().to_vec();
+        let hparams = [51864i32, 1500, 384, 6, 4, 448, 384, 6, 4, 80, 0];
+        let tables = [0i32, 0, 0];
+        let tensor = [1i32, 1, 0, 16384];
+        for value in hparams.iter().chain(&tables).chain(&tensor) {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(b"w");
+        bytes.extend((0..16384).flat_map(|i| (i as f32).to_le_bytes()));
+        bytes
+    }
+
+    /// Serve `body` over HTTP with range support, for `requests` connections
+    async fn serve(body: Vec<u8>, requests: usize) -> String {
+        serve_with(body, requests, None, None).await
+    }
+
+    /// Like `serve`, answering HEAD requests with `etag` as Hugging Face's `x-linked-etag`,
+    /// and going quiet after `stall_after` bytes of the body when set
+    async fn serve_with(
+        body: Vec<u8>,
+        requests: usize,
+        etag: Option<String>,
+        stall_after: Option<usize>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for _ in 0..requests {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![0u8; 4096];
+                let len = socket.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..len]).to_lowercase();
+
+                if request.starts_with("head ") {
+                    let etag = etag
+                        .as_ref()
+                        .map(|etag| format!("X-Linked-Etag: \"{}\"\r\n", etag))
+                        .unwrap_or_default();
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n{}\r\n",
+                        body.len(),
+                        etag
+                    );
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    continue;
+                }
+
+                let start = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+                let response = match start {
+                    Some(start) => {
+                        let mut head = format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                            body.len() - start,
+                            start,
+                            body.len() - 1,
+                            body.len()
+                        )
+                        .into_bytes();
+                        head.extend(&body[start..]);
+                        head
+                    }
+                    None => {
+                        let mut head =
+                            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len())
+                                .into_bytes();
+                        head.extend(&body[..stall_after.unwrap_or(body.len())]);
+                        head
+                    }
+                };
+                let _ = socket.write_all(&response).await;
+                if stall_after.is_some() {
+                    // Keep the connection open without sending the rest
+                    tokio::time::sleep(Duration::from_secs(3600)).await;
+                }
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    fn sha256(bytes: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(bytes);
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_resumes_and_verifies() {
+        let body = model_bytes();
+        let expected = sha256(&body);
+
+        // An earlier attempt stopped partway through
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("ggml-test.bin.part"), &body[..1000]).unwrap();
+
+        let downloader = ModelDownloader::new();
+        downloader.set_base_url(&format!("{}/", serve(body.clone(), 1).await));
+
+        let progress = Mutex::new(Vec::new());
+        let path = downloader
+            .download(dir, "ggml-test.bin", Some(&expected), |p| progress.lock().push(p))
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+        assert!(!dir.join("ggml-test.bin.part").exists());
+        let progress = progress.into_inner();
+        assert_eq!(progress.first().unwrap().downloaded, 1000);
+        assert_eq!(progress.last().unwrap().downloaded, body.len() as u64);
+        assert_eq!(progress.last().unwrap().total, Some(body.len() as u64));
+    }
+
+    #[tokio::test]
+    async fn test_checksum_mismatch_discards_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let downloader = ModelDownloader::new();
+        downloader.set_base_url(&serve(model_bytes(), 1).await);
+
+        let error = downloader
+            .download(dir, "ggml-test.bin", Some(&"0".repeat(64)), |_| {})
+            .await
+            .unwrap_err();
+
+        assert!(format!("{:#}", error).contains("Checksum mismatch"), "{:#}", error);
+        assert!(!dir.join("ggml-test.bin").exists());
+        assert!(!dir.join("ggml-test.bin.part").exists());
+        assert!(!downloader.cancel("ggml-test.bin"));
+    }
+
+    #[tokio::test]
+    async fn test_published_checksum_is_verified() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let body = model_bytes();
+        let downloader = ModelDownloader::new();
+
+        // A mirror publishing the hash of some other file
+        let wrong = sha256(b"something else");
+        downloader.set_base_url(&serve_with(body.clone(), 2, Some(wrong), None).await);
+        let error = downloader
+            .download(dir, "ggml-test.bin", None, |_| {})
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("Checksum mismatch"), "{:#}", error);
+        assert!(!dir.join("ggml-test.bin.part").exists());
+
+        downloader.set_base_url(&serve_with(body.clone(), 2, Some(sha256(&body)), None).await);
+        let path = downloader
+            .download(dir, "ggml-test.bin", None, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_checksum_fails() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let downloader = ModelDownloader::new();
+        downloader.set_base_url(&serve_with(model_bytes(), 1, None, None).await);
+
+        let error = downloader
+            .download(dir, "ggml-test.bin", None, |_| {})
+            .await
+            .unwrap_err();
+
+        assert!(format!("{:#}", error).contains("No checksum"), "{:#}", error);
+        assert!(!dir.join("ggml-test.bin").exists());
+
+        let error = downloader
+            .download(dir, "ggml-test.bin", Some("sha256:abc"), |_| {})
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("Not a SHA-256"), "{:#}", error);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stalled_download() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let body = model_bytes();
+        let downloader = Arc::new(ModelDownloader::new());
+        downloader.set_base_url(&serve_with(body.clone(), 1, None, Some(1000)).await);
+
+        let download = {
+            let downloader = Arc::clone(&downloader);
+            let dir = dir.to_path_buf();
+            let expected = sha256(&body);
+            tokio::spawn(async move {
+                downloader.download(&dir, "ggml-test.bin", Some(&expected), |_| {}).await
+            })
+        };
+
+        // Wait for the first bytes, after which the server sends nothing more
+        while std::fs::metadata(dir.join("ggml-test.bin.part")).map_or(0, |m| m.len()) < 1000 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(downloader.cancel("ggml-test.bin"));
+
+        let error = tokio::time::timeout(Duration::from_secs(5), download)
+            .await
+            .expect("cancelled download kept waiting for data")
+            .unwrap()
+            .unwrap_err();
+        assert!(error.to_string().contains("cancelled"), "{:#}", error);
+        assert_eq!(std::fs::metadata(dir.join("ggml-test.bin.part")).unwrap().len(), 1000);
+    }
+}
@@ -0,0 +1,484 @@
+//! Model file introspection module
+//! Reads GGML and GGUF headers to describe a Whisper model and catch broken files before whisper.cpp loads them
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek};
+use std::path::Path;
+
+/// "ggml" read as a little-endian u32, the magic of whisper.cpp model files
+const GGML_MAGIC: u32 = 0x6767_6d6c;
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+/// whisper.cpp folds the quantization version into `ftype` in steps of this
+const GGML_QNT_VERSION_FACTOR: i32 = 1000;
+
+/// whisper.cpp treats vocabularies at least this large as multilingual
+const MULTILINGUAL_VOCAB_SIZE: u32 = 51865;
+
+/// Alignment of the GGUF tensor data section unless `general.alignment` overrides it
+const GGUF_DEFAULT_ALIGNMENT: u64 = 32;
+
+/// GGUF metadata value types this module reads rather than skips
+const GGUF_TYPE_U32: u32 = 4;
+const GGUF_TYPE_STRING: u32 = 8;
+const GGUF_TYPE_ARRAY: u32 = 9;
+
+/// Caps on header fields, so garbage is reported as such instead of allocating gigabytes
+const MAX_STRING_LEN: u64 = 1 << 20;
+const MAX_TENSOR_DIMS: u32 = 4;
+
+/// Container format of a model file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelFormat {
+    /// whisper.cpp's own format, which the bundled whisper.cpp loads
+    Ggml,
+    /// The newer llama.cpp format, which the bundled whisper.cpp can't load
+    Gguf,
+}
+
+/// What a model file's header says about it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModelInfo {
+    pub format: ModelFormat,
+    /// "tiny", "base", "small", "medium" or "large", None when the size isn't recognised
+    pub model_type: Option<String>,
+    pub multilingual: bool,
+    /// Weight type such as "f16" or "q5_0"
+    pub quantization: String,
+    pub vocab_size: u32,
+    pub file_size: u64,
+}
+
+/// Parse a model's header and check every tensor's data is present in the file
+pub fn inspect(path: &Path) -> Result<ModelInfo> {
+    let file = File::open(path).with_context(|| format!("Failed to open model {}", path.display()))?;
+    let file_size = file.metadata().context("Failed to read model size")?.len();
+    let mut reader = ModelReader::new(file, file_size);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if u32::from_le_bytes(magic) == GGML_MAGIC {
+        inspect_ggml(&mut reader)
+    } else if &magic == GGUF_MAGIC {
+        inspect_gguf(&mut reader)
+    } else {
+        Err(anyhow::anyhow!("Not a Whisper model: unrecognised file header"))
+    }
+}
+
+/// Inspect a model and reject anything the bundled whisper.cpp can't load
+pub fn validate(path: &Path) -> Result<ModelInfo> {
+    let info = inspect(path)?;
+    if info.format == ModelFormat::Gguf {
+        return Err(anyhow::anyhow!(
+            "GGUF Whisper models aren't supported yet, use a GGML (.bin) model"
+        ));
+    }
+    Ok(info)
+}
+
+fn inspect_ggml<R: Read + Seek>(reader: &mut ModelReader<R>) -> Result<ModelInfo> {
+    // Hyperparameters, in whisper.cpp's order
+    let n_vocab = reader.read_i32()?;
+    let _n_audio_ctx = reader.read_i32()?;
+    let _n_audio_state = reader.read_i32()?;
+    let _n_audio_head = reader.read_i32()?;
+    let n_audio_layer = reader.read_i32()?;
+    let _n_text_ctx = reader.read_i32()?;
+    let _n_text_state = reader.read_i32()?;
+    let _n_text_head = reader.read_i32()?;
+    let _n_text_layer = reader.read_i32()?;
+    let _n_mels = reader.read_i32()?;
+    let ftype = reader.read_i32()? % GGML_QNT_VERSION_FACTOR;
+
+    let vocab_size = u32::try_from(n_vocab)
+        .ok()
+        .filter(|&n| n > 0)
+        .context("Not a Whisper model: invalid vocabulary size")?;
+
+    // Mel filterbank
+    let n_mel = reader.read_count()?;
+    let n_fft = reader.read_count()?;
+    reader.skip(n_mel * n_fft * 4)?;
+
+    // Vocabulary, which may list fewer tokens than the model has
+    let n_tokens = reader.read_count()?;
+    for _ in 0..n_tokens {
+        let len = reader.read_count()?;
+        reader.skip(len)?;
+    }
+
+    // Tensors run to the end of the file
+    let mut n_tensors = 0;
+    while let Some(n_dims) = reader.read_i32_or_eof()? {
+        let name_len = reader.read_count()?;
+        let ttype = reader.read_i32()?;
+        if !(1..=MAX_TENSOR_DIMS as i32).contains(&n_dims) {
+            return Err(anyhow::anyhow!("Corrupt model: tensor with {} dimensions", n_dims));
+        }
+        let dims = (0..n_dims)
+            .map(|_| reader.read_count())
+            .collect::<Result<Vec<_>>>()?;
+        reader.skip(name_len)?;
+        reader.skip(tensor_bytes(ttype as u32, &dims)?)?;
+        n_tensors += 1;
+    }
+    if n_tensors == 0 {
+        return Err(anyhow::anyhow!("Model file is truncated: no tensors"));
+    }
+
+    Ok(ModelInfo {
+        format: ModelFormat::Ggml,
+        model_type: model_type(n_audio_layer as u64).map(str::to_string),
+        multilingual: vocab_size >= MULTILINGUAL_VOCAB_SIZE,
+        quantization: file_type_name(ftype as u32),
+        vocab_size,
+        file_size: reader.file_size,
+    })
+}
+
+fn inspect_gguf<R: Read + Seek>(reader: &mut ModelReader<R>) -> Result<ModelInfo> {
+    let version = reader.read_u32()?;
+    if !(2..=3).contains(&version) {
+        return Err(anyhow::anyhow!("Unsupported GGUF version {}", version));
+    }
+    let n_tensors = reader.read_u64()?;
+    let n_kv = reader.read_u64()?;
+
+    let mut architecture = None;
+    let mut file_type = None;
+    let mut vocab_size = None;
+    let mut encoder_layers = None;
+    let mut alignment = GGUF_DEFAULT_ALIGNMENT;
+    for _ in 0..n_kv {
+        let key = reader.read_gguf_string()?;
+        let value_type = reader.read_u32()?;
+        match key.as_str() {
+            "general.architecture" if value_type == GGUF_TYPE_STRING => {
+                architecture = Some(reader.read_gguf_string()?);
+            }
+            "general.file_type" if value_type == GGUF_TYPE_U32 => {
+                file_type = Some(reader.read_u32()?);
+            }
+            "general.alignment" if value_type == GGUF_TYPE_U32 => {
+                alignment = u64::from(reader.read_u32()?).max(1);
+            }
+            "tokenizer.ggml.tokens" if value_type == GGUF_TYPE_ARRAY => {
+                let element_type = reader.read_u32()?;
+                let len = reader.read_u64()?;
+                for _ in 0..len {
+                    reader.skip_gguf_value(element_type)?;
+                }
+                vocab_size = u32::try_from(len).ok();
+            }
+            key if key.ends_with(".encoder.block_count") && value_type == GGUF_TYPE_U32 => {
+                encoder_layers = Some(reader.read_u32()?);
+            }
+            _ => reader.skip_gguf_value(value_type)?,
+        }
+    }
+
+    match architecture.as_deref() {
+        Some("whisper") => {}
+        Some(other) => {
+            return Err(anyhow::anyhow!("Not a Whisper model: {} architecture", other));
+        }
+        None => return Err(anyhow::anyhow!("Not a Whisper model: no architecture in GGUF metadata")),
+    }
+    let vocab_size = vocab_size.context("GGUF model has no tokenizer vocabulary")?;
+
+    // Tensor data follows the tensor descriptions, each at an offset into the data section
+    let mut data_len = 0;
+    for _ in 0..n_tensors {
+        let _name = reader.read_gguf_string()?;
+        let n_dims = reader.read_u32()?;
+        if n_dims == 0 || n_dims > MAX_TENSOR_DIMS {
+            return Err(anyhow::anyhow!("Corrupt model: tensor with {} dimensions", n_dims));
+        }
+        let dims = (0..n_dims)
+            .map(|_| reader.read_u64())
+            .collect::<Result<Vec<_>>>()?;
+        let ttype = reader.read_u32()?;
+        let offset = reader.read_u64()?;
+        data_len = data_len.max(offset.saturating_add(tensor_bytes(ttype, &dims)?));
+    }
+    let data_start = reader.position()?.div_ceil(alignment) * alignment;
+    if data_len > 0 && data_start.saturating_add(data_len) > reader.file_size {
+        return Err(anyhow::anyhow!("Model file is truncated"));
+    }
+
+    Ok(ModelInfo {
+        format: ModelFormat::Gguf,
+        model_type: encoder_layers.and_then(|layers| model_type(layers.into())).map(str::to_string),
+        multilingual: vocab_size >= MULTILINGUAL_VOCAB_SIZE,
+        quantization: file_type.map_or_else(|| "unknown".to_string(), file_type_name),
+        vocab_size,
+        file_size: reader.file_size,
+    })
+}
+
+/// Whisper size by encoder layer count
+fn model_type(audio_layers: u64) -> Option<&'static str> {
+    match audio_layers {
+        4 => Some("tiny"),
+        6 => Some("base"),
+        12 => Some("small"),
+        24 => Some("medium"),
+        32 => Some("large"),
+        _ => None,
+    }
+}
+
+/// Name of a whole-model file type (ggml's `ggml_ftype`)
+fn file_type_name(ftype: u32) -> String {
+    match ftype {
+        0 => "f32",
+        1 => "f16",
+        2 => "q4_0",
+        3 => "q4_1",
+        7 => "q8_0",
+        8 => "q5_0",
+        9 => "q5_1",
+        10 => "q2_k",
+        11 => "q3_k",
+        12 => "q4_k",
+        13 => "q5_k",
+        14 => "q6_k",
+        _ => return format!("unknown ({})", ftype),
+    }
+    .to_string()
+}
+
+/// Bytes of tensor data for a tensor type (ggml's `ggml_type`) and shape
+fn tensor_bytes(ttype: u32, dims: &[u64]) -> Result<u64> {
+    // (bytes per block, values per block)
+    let (block_bytes, block_len) = match ttype {
+        0 => (4, 1),
+        1 | 30 => (2, 1),
+        2 => (18, 32),
+        3 => (20, 32),
+        6 => (22, 32),
+        7 => (24, 32),
+        8 => (34, 32),
+        9 => (36, 32),
+        10 => (84, 256),
+        11 => (110, 256),
+        12 => (144, 256),
+        13 => (176, 256),
+        14 => (210, 256),
+        15 => (292, 256),
+        _ => return Err(anyhow::anyhow!("Unsupported tensor type {}", ttype)),
+    };
+    let elements = dims
+        .iter()
+        .try_fold(1u64, |acc, &dim| acc.checked_mul(dim))
+        .context("Corrupt model: tensor too large")?;
+    Ok(elements / block_len * block_bytes)
+}
+
+/// Little-endian reads that report running out of file as truncation
+struct ModelReader<R> {
+    inner: BufReader<R>,
+    file_size: u64,
+}
+
+impl<R: Read + Seek> ModelReader<R> {
+    fn new(inner: R, file_size: u64) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+            file_size,
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.inner.read_exact(buf).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => anyhow::anyhow!("Model file is truncated"),
+            _ => anyhow::Error::new(e).context("Failed to read model"),
+        })
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_i32(&mut self) -> Result<i32> {
+        Ok(self.read_u32()? as i32)
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        self.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// A non-negative i32 length or count
+    fn read_count(&mut self) -> Result<u64> {
+        let value = self.read_i32()?;
+        u64::try_from(value).map_err(|_| anyhow::anyhow!("Corrupt model: negative length {}", value))
+    }
+
+    /// Like `read_i32`, but None at a clean end of file
+    fn read_i32_or_eof(&mut self) -> Result<Option<i32>> {
+        if self.position()? == self.file_size {
+            return Ok(None);
+        }
+        self.read_i32().map(Some)
+    }
+
+    fn position(&mut self) -> Result<u64> {
+        self.inner.stream_position().context("Failed to read model")
+    }
+
+    /// Skip bytes, failing if that runs past the end of the file
+    ///
+    /// Seeks relative to the buffer so skipping a short value doesn't throw it away.
+    fn skip(&mut self, len: u64) -> Result<()> {
+        let target = self.position()?.checked_add(len);
+        match (target, i64::try_from(len)) {
+            (Some(target), Ok(offset)) if target <= self.file_size => {
+                self.inner
+                    .seek_relative(offset)
+                    .context("Failed to read model")?;
+                Ok(())
+            }
+            _ => Err(anyhow::anyhow!("Model file is truncated")),
+        }
+    }
+
+    fn read_gguf_string(&mut self) -> Result<String> {
+        let len = self.read_u64()?;
+        if len > MAX_STRING_LEN {
+            return Err(anyhow::anyhow!("Corrupt model: {} byte string", len));
+        }
+        let mut buf = vec![0u8; len as usize];
+        self.read_exact(&mut buf)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    fn skip_gguf_value(&mut self, value_type: u32) -> Result<()> {
+        match value_type {
+            // u8, i8, bool
+            0 | 1 | 7 => self.skip(1),
+            // u16, i16
+            2 | 3 => self.skip(2),
+            // u32, i32, f32
+            4..=6 => self.skip(4),
+            // u64, i64, f64
+            10..=12 => self.skip(8),
+            GGUF_TYPE_STRING => {
+                let len = self.read_u64()?;
+                self.skip(len)
+            }
+            GGUF_TYPE_ARRAY => {
+                let element_type = self.read_u32()?;
+                let len = self.read_u64()?;
+                for _ in 0..len {
+                    self.skip_gguf_value(element_type)?;
+                }
+                Ok(())
+            }
+            _ => Err(anyhow::anyhow!("Corrupt model: unknown GGUF value type {}", value_type)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tiny multilingual GGML model with one 8x2 f16 tensor, as whisper.cpp's converter writes it
+    fn ggml_model() -> Vec<u8> {
+        let mut bytes = GGML_MAGIC.to_le_bytes().to_vec();
+        let hparams = [51865, 1500, 384, 6, 4, 448, 384, 6, 4, 80, 1];
+        bytes.extend(hparams.iter().flat_map(|v: &i32| v.to_le_bytes()));
+        // 2x3 mel filters
+        bytes.extend([2i32, 3].iter().flat_map(|v| v.to_le_bytes()));
+        bytes.extend([0u8; 2 * 3 * 4]);
+        // Two vocabulary tokens
+        bytes.extend(2i32.to_le_bytes());
+        for token in [&b"hi"[..], b" there"] {
+            bytes.extend((token.len() as i32).to_le_bytes());
+            bytes.extend(token);
+        }
+        // Tensor header: dims, name length, type, shape, name, then data
+        bytes.extend([2i32, 4, 1, 8, 2].iter().flat_map(|v| v.to_le_bytes()));
+        bytes.extend(b"conv");
+        bytes.extend([0u8; 8 * 2 * 2]);
+        bytes
+    }
+
+    fn write_model(bytes: &[u8]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, bytes).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_inspect_ggml() {
+        let bytes = ggml_model();
+        let file = write_model(&bytes);
+        let info = validate(file.path()).unwrap();
+
+        assert_eq!(
+            info,
+            ModelInfo {
+                format: ModelFormat::Ggml,
+                model_type: Some("tiny".to_string()),
+                multilingual: true,
+                quantization: "f16".to_string(),
+                vocab_size: 51865,
+                file_size: bytes.len() as u64,
+            }
+        );
+    }
+
+    #[test]
+    fn test_rejects_broken_files() {
+        let bytes = ggml_model();
+        let truncated = write_model(&bytes[..bytes.len() - 1]);
+        let not_model = write_model(b"RIFF....WAVEfmt ");
+
+        let truncated_error = inspect(truncated.path()).unwrap_err().to_string();
+        let not_model_error = inspect(not_model.path()).unwrap_err().to_string();
+
+        assert!(truncated_error.contains("truncated"), "{}", truncated_error);
+        assert!(not_model_error.contains("Not a Whisper model"), "{}", not_model_error);
+    }
+
+    #[test]
+    fn test_gguf_is_inspected_but_not_loadable() {
+        let mut bytes = GGUF_MAGIC.to_vec();
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(0u64.to_le_bytes()); // tensors
+        bytes.extend(2u64.to_le_bytes()); // metadata entries
+        let string = |s: &str| {
+            let mut out = (s.len() as u64).to_le_bytes().to_vec();
+            out.extend(s.as_bytes());
+            out
+        };
+        bytes.extend(string("general.architecture"));
+        bytes.extend(GGUF_TYPE_STRING.to_le_bytes());
+        bytes.extend(string("whisper"));
+        bytes.extend(string("tokenizer.ggml.tokens"));
+        bytes.extend(GGUF_TYPE_ARRAY.to_le_bytes());
+        bytes.extend(GGUF_TYPE_STRING.to_le_bytes());
+        bytes.extend(2u64.to_le_bytes());
+        bytes.extend(string("hi"));
+        bytes.extend(string(" there"));
+
+        let file = write_model(&bytes);
+        let info = inspect(file.path()).unwrap();
+        let validated = validate(file.path());
+
+        assert_eq!(info.format, ModelFormat::Gguf);
+        assert_eq!(info.vocab_size, 2);
+        assert!(!info.multilingual);
+        assert!(validated.is_err());
+    }
+}
@@ -0,0 +1,688 @@
+//! Speech recognizer module
+//! The backend interface the voice command handler transcribes through, run locally or on a server
+
+use crate::grammar::CommandGrammar;
+use crate::whisper::{
+    self, Interrupted, Segment, Transcription, TranscriptionOptions, WhisperTranscriber, Word,
+};
+use crate::worker::Priority;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Turns 16 kHz mono audio into text
+#[async_trait]
+pub trait SpeechRecognizer: Send + Sync {
+    /// Load the model or otherwise get ready, before the first transcription
+    fn load(&self) -> Result<()>;
+
+    fn is_loaded(&self) -> bool;
+
+    /// Free dictation, unconstrained
+    async fn transcribe(
+        &self,
+        audio: Vec<f32>,
+        options: TranscriptionOptions,
+        priority: Priority,
+    ) -> Result<Transcription>;
+
+    /// Command mode, steered towards the utterances `grammar` accepts where the backend can;
+    /// the others transcribe freely and leave matching to the command map
+    async fn transcribe_command(
+        &self,
+        audio: Vec<f32>,
+        options: TranscriptionOptions,
+        _grammar: CommandGrammar,
+    ) -> Result<Transcription> {
+        self.transcribe(audio, options, Priority::Command).await
+    }
+
+    /// Stop command and file transcriptions in progress, returning how many were stopped
+    /// Their callers get an `Interrupted::Cancelled` error; wake word checks carry on
+    fn cancel(&self) -> usize;
+
+    /// Model file in use, None for backends that don't load one locally
+    fn model_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Load another model in place of the current one
+    fn switch_model(&self, _model_path: PathBuf) -> Result<()> {
+        Err(anyhow::anyhow!(
+            "This speech recognizer can't switch models"
+        ))
+    }
+}
+
+#[async_trait]
+impl SpeechRecognizer for WhisperTranscriber {
+    fn load(&self) -> Result<()> {
+        self.load_model()
+    }
+
+    fn is_loaded(&self) -> bool {
+        WhisperTranscriber::is_loaded(self)
+    }
+
+    async fn transcribe(
+        &self,
+        audio: Vec<f32>,
+        options: TranscriptionOptions,
+        priority: Priority,
+    ) -> Result<Transcription> {
+        WhisperTranscriber::transcribe(self, audio, options, priority).await
+    }
+
+    async fn transcribe_command(
+        &self,
+        audio: Vec<f32>,
+        options: TranscriptionOptions,
+        grammar: CommandGrammar,
+    ) -> Result<Transcription> {
+        WhisperTranscriber::transcribe_command(self, audio, options, grammar).await
+    }
+
+    fn cancel(&self) -> usize {
+        WhisperTranscriber::cancel(self)
+    }
+
+    fn model_path(&self) -> Option<PathBuf> {
+        Some(WhisperTranscriber::model_path(self))
+    }
+
+    fn switch_model(&self, model_path: PathBuf) -> Result<()> {
+        WhisperTranscriber::switch_model(self, model_path)
+    }
+}
+
+/// Model requested from servers when none is configured, as OpenAI names it
+pub const DEFAULT_REMOTE_MODEL: &str = "whisper-1";
+
+/// Whisper's languages as its code, ISO 639-1 where there is one, and the name OpenAI's API
+/// reports instead
+const LANGUAGES: &[(&str, &str)] = &[
+    ("en", "english"),
+    ("zh", "chinese"),
+    ("de", "german"),
+    ("es", "spanish"),
+    ("ru", "russian"),
+    ("ko", "korean"),
+    ("fr", "french"),
+    ("ja", "japanese"),
+    ("pt", "portuguese"),
+    ("tr", "turkish"),
+    ("pl", "polish"),
+    ("ca", "catalan"),
+    ("nl", "dutch"),
+    ("ar", "arabic"),
+    ("sv", "swedish"),
+    ("it", "italian"),
+    ("id", "indonesian"),
+    ("hi", "hindi"),
+    ("fi", "finnish"),
+    ("vi", "vietnamese"),
+    ("he", "hebrew"),
+    ("uk", "ukrainian"),
+    ("el", "greek"),
+    ("ms", "malay"),
+    ("cs", "czech"),
+    ("ro", "romanian"),
+    ("da", "danish"),
+    ("hu", "hungarian"),
+    ("ta", "tamil"),
+    ("no", "norwegian"),
+    ("th", "thai"),
+    ("ur", "urdu"),
+    ("hr", "croatian"),
+    ("bg", "bulgarian"),
+    ("lt", "lithuanian"),
+    ("la", "latin"),
+    ("mi", "maori"),
+    ("ml", "malayalam"),
+    ("cy", "welsh"),
+    ("sk", "slovak"),
+    ("te", "telugu"),
+    ("fa", "persian"),
+    ("lv", "latvian"),
+    ("bn", "bengali"),
+    ("sr", "serbian"),
+    ("az", "azerbaijani"),
+    ("sl", "slovenian"),
+    ("kn", "kannada"),
+    ("et", "estonian"),
+    ("mk", "macedonian"),
+    ("br", "breton"),
+    ("eu", "basque"),
+    ("is", "icelandic"),
+    ("hy", "armenian"),
+    ("ne", "nepali"),
+    ("mn", "mongolian"),
+    ("bs", "bosnian"),
+    ("kk", "kazakh"),
+    ("sq", "albanian"),
+    ("sw", "swahili"),
+    ("gl", "galician"),
+    ("mr", "marathi"),
+    ("pa", "punjabi"),
+    ("si", "sinhala"),
+    ("km", "khmer"),
+    ("sn", "shona"),
+    ("yo", "yoruba"),
+    ("so", "somali"),
+    ("af", "afrikaans"),
+    ("oc", "occitan"),
+    ("ka", "georgian"),
+    ("be", "belarusian"),
+    ("tg", "tajik"),
+    ("sd", "sindhi"),
+    ("gu", "gujarati"),
+    ("am", "amharic"),
+    ("yi", "yiddish"),
+    ("lo", "lao"),
+    ("uz", "uzbek"),
+    ("fo", "faroese"),
+    ("ht", "haitian creole"),
+    ("ps", "pashto"),
+    ("tk", "turkmen"),
+    ("nn", "nynorsk"),
+    ("mt", "maltese"),
+    ("sa", "sanskrit"),
+    ("lb", "luxembourgish"),
+    ("my", "myanmar"),
+    ("bo", "tibetan"),
+    ("tl", "tagalog"),
+    ("mg", "malagasy"),
+    ("as", "assamese"),
+    ("tt", "tatar"),
+    ("haw", "hawaiian"),
+    ("ln", "lingala"),
+    ("ha", "hausa"),
+    ("ba", "bashkir"),
+    ("jw", "javanese"),
+    ("su", "sundanese"),
+    ("yue", "cantonese"),
+];
+
+/// Transcribes on a server speaking OpenAI's `/v1/audio/transcriptions` API,
+/// such as faster-whisper-server or a whisper.cpp server on a faster machine
+///
+/// Command grammars aren't supported, so command mode relies on the command map alone.
+pub struct HttpRecognizer {
+    client: reqwest::Client,
+    /// Server root, without the `/v1/...` path
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    loaded: AtomicBool,
+    /// Wakes the cancellable requests in flight when `cancel` is called
+    cancelled: Notify,
+    cancellable: AtomicUsize,
+}
+
+impl HttpRecognizer {
+    pub fn new(base_url: &str, api_key: Option<String>, model: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim().trim_end_matches('/').to_string(),
+            api_key: api_key.filter(|key| !key.is_empty()),
+            model: model
+                .filter(|model| !model.is_empty())
+                .unwrap_or_else(|| DEFAULT_REMOTE_MODEL.to_string()),
+            loaded: AtomicBool::new(false),
+            cancelled: Notify::new(),
+            cancellable: AtomicUsize::new(0),
+        }
+    }
+
+    async fn request(
+        &self,
+        audio: &[f32],
+        options: &TranscriptionOptions,
+    ) -> Result<Transcription> {
+        let file = Part::bytes(encode_wav(audio)?)
+            .file_name("audio.wav")
+            .mime_str("audio/wav")
+            .context("Failed to encode audio")?;
+        let mut form = Form::new()
+            .text("model", self.model.clone())
+            .text("response_format", "verbose_json")
+            .text("temperature", options.temperature.to_string())
+            .text("timestamp_granularities[]", "segment");
+        if options.word_timestamps {
+            form = form.text("timestamp_granularities[]", "word");
+        }
+        if let Some(language) = &options.language {
+            form = form.text("language", language.clone());
+        }
+        if let Some(prompt) = options.initial_prompt.as_deref().filter(|p| !p.is_empty()) {
+            form = form.text("prompt", prompt.to_string());
+        }
+
+        let mut request = self
+            .client
+            .post(format!("{}/v1/audio/transcriptions", self.base_url))
+            .multipart(form.part("file", file));
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request
+            .send()
+            .await
+            .context("Failed to reach transcription server")?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!(
+                "Transcription server returned {}: {}",
+                status,
+                body.trim()
+            ));
+        }
+
+        let body = response
+            .bytes()
+            .await
+            .context("Failed to read transcription server response")?;
+        let response: VerboseTranscription = serde_json::from_slice(&body)
+            .context("Transcription server sent an unexpected response")?;
+        Ok(response.into_transcription(options))
+    }
+}
+
+#[async_trait]
+impl SpeechRecognizer for HttpRecognizer {
+    fn load(&self) -> Result<()> {
+        if !self.base_url.starts_with("http://") && !self.base_url.starts_with("https://") {
+            return Err(anyhow::anyhow!(
+                "Transcription server URL must start with http:// or https://"
+            ));
+        }
+
+        self.loaded.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.loaded.load(Ordering::SeqCst)
+    }
+
+    async fn transcribe(
+        &self,
+        audio: Vec<f32>,
+        options: TranscriptionOptions,
+        priority: Priority,
+    ) -> Result<Transcription> {
+        let request = async {
+            match options.timeout_ms.map(Duration::from_millis) {
+                Some(timeout) => tokio::time::timeout(timeout, self.request(&audio, &options))
+                    .await
+                    .map_err(|_| Interrupted::TimedOut)?,
+                None => self.request(&audio, &options).await,
+            }
+        };
+
+        // Wake word checks repeat every hop, so cancelling them would only stall the listener
+        if priority == Priority::WakeWord {
+            return request.await;
+        }
+
+        let cancelled = self.cancelled.notified();
+        self.cancellable.fetch_add(1, Ordering::SeqCst);
+        let result = tokio::select! {
+            result = request => result,
+            _ = cancelled => Err(Interrupted::Cancelled.into()),
+        };
+        self.cancellable.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    fn cancel(&self) -> usize {
+        let count = self.cancellable.load(Ordering::SeqCst);
+        self.cancelled.notify_waiters();
+        count
+    }
+}
+
+/// Response to a `verbose_json` transcription request
+#[derive(Debug, Deserialize)]
+struct VerboseTranscription {
+    text: String,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    segments: Vec<VerboseSegment>,
+    #[serde(default)]
+    words: Vec<VerboseWord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerboseSegment {
+    start: f64,
+    end: f64,
+    text: String,
+    #[serde(default)]
+    avg_logprob: f32,
+    #[serde(default)]
+    no_speech_prob: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerboseWord {
+    word: String,
+    start: f64,
+    end: f64,
+    /// Reported by faster-whisper, not by OpenAI
+    #[serde(default)]
+    probability: Option<f32>,
+}
+
+impl VerboseTranscription {
+    fn into_transcription(self, options: &TranscriptionOptions) -> Transcription {
+        let words = self.words;
+        let segments = self
+            .segments
+            .into_iter()
+            .map(|segment| {
+                let avg_prob = segment.avg_logprob.exp().clamp(0.0, 1.0);
+                let (start_ms, end_ms) = (seconds_to_ms(segment.start), seconds_to_ms(segment.end));
+                let segment_words = words
+                    .iter()
+                    .filter(|word| {
+                        let start = seconds_to_ms(word.start);
+                        start >= start_ms && start < end_ms
+                    })
+                    .map(|word| Word {
+                        text: word.word.trim().to_string(),
+                        start_ms: seconds_to_ms(word.start),
+                        end_ms: seconds_to_ms(word.end),
+                        probability: word.probability.unwrap_or(avg_prob),
+                    })
+                    .collect();
+
+                let segment_result = Segment {
+                    start_ms,
+                    end_ms,
+                    text: segment.text.trim().to_string(),
+                    avg_prob,
+                    no_speech_prob: segment.no_speech_prob,
+                    words: segment_words,
+                };
+                (segment_result, vec![segment.avg_logprob])
+            })
+            .collect();
+
+        // OpenAI names the language ("english") where whisper.cpp servers give the code
+        let language = match (&options.language, self.language) {
+            (Some(requested), _) => requested.clone(),
+            (None, Some(language)) => language_code(&language),
+            (None, None) => "en".to_string(),
+        };
+
+        whisper::assemble(
+            &self.text,
+            segments,
+            language,
+            None,
+            options.filter_hallucinations,
+        )
+    }
+}
+
+/// Whisper's code for a language a server reported as either its name or its code
+fn language_code(language: &str) -> String {
+    let language = language.trim().to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(_, name)| *name == language)
+        .map_or(language, |(code, _)| code.to_string())
+}
+
+fn seconds_to_ms(seconds: f64) -> u64 {
+    (seconds.max(0.0) * 1000.0).round() as u64
+}
+
+/// 16-bit PCM WAV of 16 kHz mono audio
+fn encode_wav(audio: &[f32]) -> Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 16000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut wav = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut wav, spec).context("Failed to encode audio")?;
+    for &sample in audio {
+        writer
+            .write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .context("Failed to encode audio")?;
+    }
+    writer.finalize().context("Failed to encode audio")?;
+
+    Ok(wav.into_inner())
+}
+
+/// Recognizer returning scripted text, for exercising the pipeline without a model
+#[cfg(test)]
+pub struct MockRecognizer {
+    responses: parking_lot::Mutex<std::collections::VecDeque<Result<String, String>>>,
+    /// Length and priority of every clip submitted
+    pub calls: parking_lot::Mutex<Vec<(usize, Priority)>>,
+}
+
+#[cfg(test)]
+impl MockRecognizer {
+    /// Answers each transcription with the next of `responses`, then with empty text
+    pub fn new(responses: &[&str]) -> Self {
+        let responses: Vec<_> = responses.iter().map(|&r| Ok(r)).collect();
+        Self::with_failures(&responses)
+    }
+
+    /// Like `new`, failing the transcriptions whose response is an `Err` with that message
+    pub fn with_failures(responses: &[Result<&str, &str>]) -> Self {
+        Self {
+            responses: parking_lot::Mutex::new(
+                responses
+                    .iter()
+                    .map(|r| r.map(str::to_string).map_err(str::to_string))
+                    .collect(),
+            ),
+            calls: parking_lot::Mutex::new(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl SpeechRecognizer for MockRecognizer {
+    fn load(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn is_loaded(&self) -> bool {
+        true
+    }
+
+    async fn transcribe(
+        &self,
+        audio: Vec<f32>,
+        options: TranscriptionOptions,
+        priority: Priority,
+    ) -> Result<Transcription> {
+        self.calls.lock().push((audio.len(), priority));
+        let text = self
+            .responses
+            .lock()
+            .pop_front()
+            .unwrap_or_else(|| Ok(String::new()))
+            .map_err(|message| anyhow::anyhow!(message))?;
+        let segment = Segment {
+            start_ms: 0,
+            end_ms: (audio.len() / 16) as u64,
+            text: text.clone(),
+            avg_prob: 0.9,
+            no_speech_prob: None,
+            words: Vec::new(),
+        };
+
+        Ok(whisper::assemble(
+            &text,
+            vec![(segment, vec![0.9f32.ln()])],
+            options.language.unwrap_or_else(|| "en".to_string()),
+            None,
+            options.filter_hallucinations,
+        ))
+    }
+
+    fn cancel(&self) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answer one request with `body` as JSON, handing back the request it received
+    async fn serve_once(body: &'static str) -> (String, tokio::task::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 8192];
+            loop {
+                let len = socket.read(&mut buffer).await.unwrap();
+                request.extend(&buffer[..len]);
+                let text = String::from_utf8_lossy(&request).to_lowercase();
+                let Some(head_end) = text.find("\r\n\r\n") else {
+                    continue;
+                };
+                let content_length = text
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .and_then(|length| length.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+                if len == 0 || request.len() >= head_end + 4 + content_length {
+                    break;
+                }
+            }
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            request
+        });
+        (format!("http://{}/", address), server)
+    }
+
+    #[tokio::test]
+    async fn test_http_recognizer() {
+        let (url, server) = serve_once(
+            r#"{"text": " Start the workflow. Thank you.", "language": "english", "segments": [
+                {"start": 0.0, "end": 1.5, "text": " Start the workflow.", "avg_logprob": -0.2, "no_speech_prob": 0.01},
+                {"start": 1.5, "end": 3.0, "text": " Thank you.", "avg_logprob": -1.4, "no_speech_prob": 0.8}
+            ]}"#,
+        )
+        .await;
+
+        // Quotes, line breaks and dashes that would break a hand-built body
+        let options = TranscriptionOptions {
+            initial_prompt: Some("Say \"stop\"\r\n--kiku".to_string()),
+            ..TranscriptionOptions::default()
+        };
+        let recognizer = HttpRecognizer::new(&url, Some("secret".to_string()), None);
+        recognizer.load().unwrap();
+        let transcription =
+            SpeechRecognizer::transcribe(&recognizer, vec![0.0; 16000], options, Priority::Command)
+                .await
+                .unwrap();
+
+        assert_eq!(transcription.text, "Start the workflow.");
+        assert_eq!(transcription.language, "en");
+        assert_eq!(transcription.segments[0].end_ms, 1500);
+        assert!((transcription.confidence - (-0.2f32).exp()).abs() < 1e-5);
+        assert_eq!(transcription.filtered.len(), 1);
+
+        let request = String::from_utf8_lossy(&server.await.unwrap()).to_string();
+        assert!(request.starts_with("POST /v1/audio/transcriptions "));
+        assert!(request
+            .to_lowercase()
+            .contains("authorization: bearer secret"));
+        assert!(request.contains("name=\"model\"\r\n\r\nwhisper-1\r\n"));
+        assert!(request.contains("name=\"response_format\"\r\n\r\nverbose_json\r\n"));
+        assert!(request.contains("filename=\"audio.wav\""));
+        assert!(request.contains("name=\"prompt\"\r\n\r\nSay \"stop\"\r\n--kiku\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_http_recognizer_language_names() {
+        let (url, _server) = serve_once(
+            r#"{"text": "Starte den Arbeitsablauf.", "language": "german", "segments": [
+                {"start": 0.0, "end": 1.5, "text": "Starte den Arbeitsablauf.", "avg_logprob": -0.2}
+            ]}"#,
+        )
+        .await;
+
+        // Detected rather than requested, so the server's name for it is all there is
+        let options = TranscriptionOptions {
+            language: None,
+            ..TranscriptionOptions::default()
+        };
+        let recognizer = HttpRecognizer::new(&url, None, None);
+        let transcription =
+            SpeechRecognizer::transcribe(&recognizer, vec![0.0; 16000], options, Priority::Command)
+                .await
+                .unwrap();
+
+        assert_eq!(transcription.language, "de");
+        assert_eq!(language_code("Japanese"), "ja");
+        assert_eq!(language_code("ja"), "ja");
+    }
+
+    #[tokio::test]
+    async fn test_http_recognizer_cancel() {
+        // Accepts the connection but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let _server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            drop(socket);
+        });
+
+        let recognizer = std::sync::Arc::new(HttpRecognizer::new(&url, None, None));
+        let transcription = {
+            let recognizer = std::sync::Arc::clone(&recognizer);
+            tokio::spawn(async move {
+                SpeechRecognizer::transcribe(
+                    recognizer.as_ref(),
+                    vec![0.0; 1600],
+                    TranscriptionOptions::default(),
+                    Priority::File,
+                )
+                .await
+            })
+        };
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(recognizer.cancel(), 1);
+        let error = transcription.await.unwrap().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Interrupted>(),
+            Some(&Interrupted::Cancelled)
+        );
+    }
+}
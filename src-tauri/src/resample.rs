@@ -0,0 +1,263 @@
+//! Resampling module
+//! Band-limited polyphase windowed-sinc resampler for arbitrary rational ratios
+
+/// Sample rate Whisper expects its input at
+pub const WHISPER_SAMPLE_RATE: u32 = 16000;
+
+/// Zero crossings of the sinc kept on each side of the kernel centre
+const ZERO_CROSSINGS: usize = 16;
+
+/// Passband edge as a fraction of the output Nyquist frequency
+const ROLLOFF: f64 = 0.92;
+
+/// Streaming resampler
+///
+/// Feed input with [`Resampler::process`] in chunks of any size and call
+/// [`Resampler::flush`] once the input ends to drain the filter tail.
+pub struct Resampler {
+    /// Upsampling factor (output rate / gcd)
+    up: u64,
+    /// Downsampling factor (input rate / gcd)
+    down: u64,
+    /// Kernel half-width in input samples
+    half_width: usize,
+    /// Filter taps, one row of `2 * half_width` per output phase
+    phases: Vec<Vec<f32>>,
+    /// Pending input, `buffer[0]` is absolute input sample `buffer_start`
+    buffer: Vec<f32>,
+    buffer_start: i64,
+    /// Index of the next output sample
+    next_output: u64,
+    /// Total input samples received
+    input_len: u64,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        let divisor = gcd(from_rate.max(1) as u64, to_rate.max(1) as u64);
+        let up = to_rate.max(1) as u64 / divisor;
+        let down = from_rate.max(1) as u64 / divisor;
+
+        // Cutoff relative to the input Nyquist, lowered when decimating
+        let cutoff = (up as f64 / down as f64).min(1.0) * ROLLOFF;
+        let half_width = (ZERO_CROSSINGS as f64 / cutoff).ceil() as usize;
+
+        let phases = (0..up)
+            .map(|phase| {
+                let frac = phase as f64 / up as f64;
+                let mut taps: Vec<f64> = (0..2 * half_width)
+                    .map(|k| {
+                        // Distance from the output instant to input sample k
+                        let distance = k as f64 - (half_width as f64 - 1.0) - frac;
+                        cutoff * sinc(cutoff * distance) * blackman(distance / half_width as f64)
+                    })
+                    .collect();
+
+                // Unity gain at DC for every phase
+                let sum: f64 = taps.iter().sum();
+                if sum.abs() > f64::EPSILON {
+                    taps.iter_mut().for_each(|t| *t /= sum);
+                }
+
+                taps.into_iter().map(|t| t as f32).collect()
+            })
+            .collect();
+
+        Self {
+            up,
+            down,
+            half_width,
+            phases,
+            // Leading zeros so the first output is centred on the first input sample
+            buffer: vec![0.0; half_width - 1],
+            buffer_start: -(half_width as i64 - 1),
+            next_output: 0,
+            input_len: 0,
+        }
+    }
+
+    /// Resample a chunk of input, returning every output sample that is fully determined so far
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        self.input_len += input.len() as u64;
+        self.buffer.extend_from_slice(input);
+        self.drain(u64::MAX)
+    }
+
+    /// Drain the filter tail once the input is finished
+    /// The resampler is reset afterwards and can be reused
+    pub fn flush(&mut self) -> Vec<f32> {
+        let expected = (self.input_len * self.up).div_ceil(self.down);
+        self.buffer.resize(self.buffer.len() + self.half_width, 0.0);
+        let output = self.drain(expected);
+        self.reset();
+        output
+    }
+
+    /// Discard all buffered input and start over
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.buffer.resize(self.half_width - 1, 0.0);
+        self.buffer_start = -(self.half_width as i64 - 1);
+        self.next_output = 0;
+        self.input_len = 0;
+    }
+
+    fn drain(&mut self, limit: u64) -> Vec<f32> {
+        let taps = 2 * self.half_width;
+        let buffer_end = self.buffer_start + self.buffer.len() as i64;
+        let mut output = Vec::new();
+
+        while self.next_output < limit {
+            let position = self.next_output * self.down;
+            let center = (position / self.up) as i64;
+            let phase = (position % self.up) as usize;
+
+            // Kernel covers input samples center - half_width + 1 ..= center + half_width
+            let first = center - self.half_width as i64 + 1;
+            if first + taps as i64 > buffer_end {
+                break;
+            }
+
+            let offset = (first - self.buffer_start) as usize;
+            let window = &self.buffer[offset..offset + taps];
+            let sample = window
+                .iter()
+                .zip(&self.phases[phase])
+                .map(|(x, h)| x * h)
+                .sum();
+
+            output.push(sample);
+            self.next_output += 1;
+        }
+
+        // Drop input no future output can reach
+        let next_center = (self.next_output * self.down / self.up) as i64;
+        let keep_from = next_center - self.half_width as i64 + 1;
+        let consumed = (keep_from - self.buffer_start).clamp(0, self.buffer.len() as i64) as usize;
+        self.buffer.drain(..consumed);
+        self.buffer_start += consumed as i64;
+
+        output
+    }
+}
+
+/// Resample a complete buffer in one go
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate {
+        return samples.to_vec();
+    }
+
+    let mut resampler = Resampler::new(from_rate, to_rate);
+    let mut output = resampler.process(samples);
+    output.extend(resampler.flush());
+    output
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        px.sin() / px
+    }
+}
+
+/// Blackman window over [-1, 1], zero outside
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let a = std::f64::consts::PI * x;
+    0.42 + 0.5 * a.cos() + 0.08 * (2.0 * a).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, rate: u32, seconds: f32) -> Vec<f32> {
+        let len = (rate as f32 * seconds) as usize;
+        (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / rate as f32).sin())
+            .collect()
+    }
+
+    /// RMS of the signal ignoring filter warm-up at both ends
+    fn steady_rms(samples: &[f32]) -> f32 {
+        let edge = samples.len() / 10;
+        let body = &samples[edge..samples.len() - edge];
+        (body.iter().map(|s| s * s).sum::<f32>() / body.len() as f32).sqrt()
+    }
+
+    /// The nearest-neighbour decimation this module replaced
+    fn decimate_nearest(samples: &[f32], from_rate: u32) -> Vec<f32> {
+        let ratio = from_rate as f32 / 16000.0;
+        let new_length = (samples.len() as f32 / ratio) as usize;
+        (0..new_length)
+            .map(|i| samples[((i as f32 * ratio) as usize).min(samples.len() - 1)])
+            .collect()
+    }
+
+    #[test]
+    fn test_output_length() {
+        for &rate in &[8000, 16000, 22050, 44100, 48000] {
+            let input = vec![0.0; rate as usize];
+            assert_eq!(resample(&input, rate, 16000).len(), 16000, "from {} Hz", rate);
+        }
+    }
+
+    #[test]
+    fn test_passband_preserved() {
+        for &rate in &[44100, 48000] {
+            let output = resample(&sine(1000.0, rate, 1.0), rate, WHISPER_SAMPLE_RATE);
+            let rms = steady_rms(&output);
+            assert!((rms - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.01, "rms {} from {} Hz", rms, rate);
+        }
+    }
+
+    #[test]
+    fn test_aliasing_suppressed() {
+        // Tones above 8 kHz must vanish instead of folding back into the speech band
+        for &(rate, freq) in &[(48000, 11000.0), (44100, 12000.0), (48000, 9500.0)] {
+            let input = sine(freq, rate, 1.0);
+            let old = steady_rms(&decimate_nearest(&input, rate));
+            let new = steady_rms(&resample(&input, rate, WHISPER_SAMPLE_RATE));
+
+            assert!(old > 0.5, "nearest-neighbour should alias, got {}", old);
+            assert!(new < old * 0.01, "aliasing {} vs {} for {} Hz at {} Hz", new, old, freq, rate);
+        }
+    }
+
+    #[test]
+    fn test_streaming_matches_one_shot() {
+        let input = sine(440.0, 44100, 0.5);
+        let expected = resample(&input, 44100, 16000);
+
+        let mut resampler = Resampler::new(44100, 16000);
+        let mut streamed = Vec::new();
+        for chunk in input.chunks(333) {
+            streamed.extend(resampler.process(chunk));
+        }
+        streamed.extend(resampler.flush());
+
+        assert_eq!(streamed.len(), expected.len());
+        for (a, b) in streamed.iter().zip(&expected) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_upsampling() {
+        let output = resample(&sine(1000.0, 8000, 1.0), 8000, 16000);
+        assert_eq!(output.len(), 16000);
+        assert!((steady_rms(&output) - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.01);
+    }
+}
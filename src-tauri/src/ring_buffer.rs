@@ -0,0 +1,155 @@
+//! Ring buffer module
+//! Fixed-capacity sample store for always-on capture
+
+/// Keeps the most recent `capacity` samples, overwriting the oldest
+///
+/// Every sample gets an absolute position (the count of samples written before
+/// it) so readers can ask for "everything since position N" even after the
+/// buffer has wrapped.
+pub struct RingBuffer {
+    data: Vec<f32>,
+    /// Index in `data` the next sample is written to
+    head: usize,
+    len: usize,
+    /// Total samples ever written
+    written: u64,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: vec![0.0; capacity],
+            head: 0,
+            len: 0,
+            written: 0,
+        }
+    }
+
+    /// Resize the buffer, discarding its contents
+    /// Positions keep counting from where they were
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.data = vec![0.0; capacity];
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Position one past the newest sample
+    pub fn end_position(&self) -> u64 {
+        self.written
+    }
+
+    /// Position of the oldest sample still held
+    pub fn start_position(&self) -> u64 {
+        self.written - self.len as u64
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        let capacity = self.capacity();
+        if capacity == 0 {
+            self.written += samples.len() as u64;
+            return;
+        }
+
+        // Only the tail can survive if the input alone overflows the buffer
+        let skipped = samples.len().saturating_sub(capacity);
+        for &sample in &samples[skipped..] {
+            self.data[self.head] = sample;
+            self.head = (self.head + 1) % capacity;
+        }
+
+        self.len = (self.len + samples.len()).min(capacity);
+        self.written += samples.len() as u64;
+    }
+
+    /// Discard the contents, keeping positions and capacity
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Samples from `position` onwards, clamped to what is still held
+    pub fn since(&self, position: u64) -> Vec<f32> {
+        let position = position.clamp(self.start_position(), self.end_position());
+        self.latest((self.written - position) as usize)
+    }
+
+    /// The most recent `count` samples, oldest first
+    pub fn latest(&self, count: usize) -> Vec<f32> {
+        let count = count.min(self.len);
+        let capacity = self.capacity();
+        let first = (self.head + capacity - count) % capacity.max(1);
+
+        let mut samples = Vec::with_capacity(count);
+        if first + count <= capacity {
+            samples.extend_from_slice(&self.data[first..first + count]);
+        } else {
+            samples.extend_from_slice(&self.data[first..]);
+            samples.extend_from_slice(&self.data[..count - (capacity - first)]);
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wraparound() {
+        let mut ring = RingBuffer::new(4);
+        ring.push(&[1.0, 2.0, 3.0]);
+        assert_eq!(ring.latest(10), vec![1.0, 2.0, 3.0]);
+
+        ring.push(&[4.0, 5.0, 6.0]);
+        assert_eq!(ring.len(), 4);
+        assert_eq!(ring.latest(4), vec![3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(ring.latest(2), vec![5.0, 6.0]);
+        assert_eq!(ring.start_position(), 2);
+        assert_eq!(ring.end_position(), 6);
+    }
+
+    #[test]
+    fn test_oversized_push() {
+        let mut ring = RingBuffer::new(3);
+        ring.push(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(ring.latest(3), vec![3.0, 4.0, 5.0]);
+        assert_eq!(ring.end_position(), 5);
+    }
+
+    #[test]
+    fn test_since_position() {
+        let mut ring = RingBuffer::new(5);
+        ring.push(&[1.0, 2.0, 3.0]);
+        let mark = ring.end_position();
+        ring.push(&[4.0, 5.0, 6.0]);
+
+        assert_eq!(ring.since(mark), vec![4.0, 5.0, 6.0]);
+        assert_eq!(ring.since(mark - 1), vec![3.0, 4.0, 5.0, 6.0]);
+        // Positions that were overwritten clamp to the oldest sample
+        assert_eq!(ring.since(0), vec![2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert!(ring.since(100).is_empty());
+    }
+
+    #[test]
+    fn test_capacity_change_keeps_positions() {
+        let mut ring = RingBuffer::new(2);
+        ring.push(&[1.0, 2.0]);
+        ring.set_capacity(3);
+
+        assert!(ring.is_empty());
+        assert_eq!(ring.start_position(), 2);
+        ring.push(&[7.0]);
+        assert_eq!(ring.since(0), vec![7.0]);
+    }
+}
@@ -0,0 +1,120 @@
+//! Streaming transcription module
+//! Splits repeated decodes of a growing recording into text that has settled and text that may still change
+
+use serde::{Deserialize, Serialize};
+
+/// Payload of the `partial_transcript` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialTranscript {
+    /// Words two consecutive decodes agreed on, never retracted within an utterance
+    pub stable: String,
+    /// The rest of the latest decode, likely to change as more audio arrives
+    pub unstable: String,
+    /// Set on the last event of an utterance, once VAD has ended it
+    pub is_final: bool,
+}
+
+/// Local agreement between consecutive hypotheses
+///
+/// A word becomes stable once two decodes in a row produce it at the same position.
+#[derive(Debug, Default)]
+pub struct StabilityTracker {
+    stable: Vec<String>,
+    previous: Vec<String>,
+}
+
+impl StabilityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fold in the latest decode of the whole utterance so far
+    pub fn update(&mut self, hypothesis: &str) -> PartialTranscript {
+        let words: Vec<String> = hypothesis.split_whitespace().map(str::to_string).collect();
+
+        // Only grow the stable prefix, and only while the new decode still agrees with it
+        let agreed = common_prefix(&self.previous, &words);
+        if agreed > self.stable.len() && common_prefix(&self.stable, &words) == self.stable.len() {
+            self.stable.extend_from_slice(&words[self.stable.len()..agreed]);
+        }
+
+        let unstable = if common_prefix(&self.stable, &words) == self.stable.len() {
+            words[self.stable.len()..].join(" ")
+        } else {
+            // The decode went back on stable words; show it without retracting them
+            words.join(" ")
+        };
+        self.previous = words;
+
+        PartialTranscript {
+            stable: self.stable.join(" "),
+            unstable,
+            is_final: false,
+        }
+    }
+
+    /// The closing event, carrying the full-clip transcription
+    pub fn finish(&mut self, text: &str) -> PartialTranscript {
+        *self = Self::default();
+        PartialTranscript {
+            stable: text.trim().to_string(),
+            unstable: String::new(),
+            is_final: true,
+        }
+    }
+}
+
+/// Number of leading words two hypotheses share, ignoring case and punctuation
+fn common_prefix(a: &[String], b: &[String]) -> usize {
+    a.iter()
+        .zip(b)
+        .take_while(|(a, b)| normalize(a) == normalize(b))
+        .count()
+}
+
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_words_stabilize_on_agreement() {
+        let mut tracker = StabilityTracker::new();
+
+        let first = tracker.update("Start the");
+        assert_eq!(first.stable, "");
+        assert_eq!(first.unstable, "Start the");
+
+        // "Start" agrees, "the" became "that"
+        let second = tracker.update("Start that work");
+        assert_eq!(second.stable, "Start");
+        assert_eq!(second.unstable, "that work");
+
+        let third = tracker.update("start that workflow.");
+        assert_eq!(third.stable, "Start that");
+        assert_eq!(third.unstable, "workflow.");
+    }
+
+    #[test]
+    fn test_stable_text_is_never_retracted() {
+        let mut tracker = StabilityTracker::new();
+        tracker.update("show the status");
+        tracker.update("show the status report");
+
+        // A later decode disagreeing with stable words leaves them in place
+        let partial = tracker.update("so the status");
+        assert_eq!(partial.stable, "show the status");
+        assert_eq!(partial.unstable, "so the status");
+
+        let last = tracker.finish(" Show the status report. ");
+        assert!(last.is_final);
+        assert_eq!(last.stable, "Show the status report.");
+        assert_eq!(tracker.update("next").stable, "");
+    }
+}
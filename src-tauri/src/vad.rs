@@ -1,31 +1,92 @@
-/// Voice Activity Detection (VAD) module
-/// Detects speech vs silence in audio based on energy levels
+//! Voice Activity Detection (VAD) module
+//! Detects speech vs silence in audio based on energy above a fixed threshold or the noise floor
 
-use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// How far above the noise floor speech has to be in adaptive mode
+pub const DEFAULT_MARGIN_DB: f32 = 10.0;
+
+/// Recent audio the noise floor is estimated from
+pub const NOISE_HISTORY_MS: u32 = 5000;
+
+/// Audio needed before the noise floor estimate is trusted
+const MIN_NOISE_HISTORY_MS: u32 = 300;
+
+/// Fraction of recent frames quieter than the noise floor
+/// Low enough that the pauses between words are the floor, even while someone is talking
+const NOISE_PERCENTILE: f32 = 0.1;
+
+/// Floor used for digital silence and before enough audio has been heard, about -66 dBFS
+const MIN_NOISE_FLOOR: f32 = 0.0005;
+
+/// How the energy that counts as speech is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ThresholdMode {
+    /// A fixed RMS energy, whatever the room sounds like
+    Fixed { threshold: f32 },
+    /// A margin in dB above the tracked ambient noise floor
+    Adaptive { margin_db: f32 },
+}
+
+impl Default for ThresholdMode {
+    fn default() -> Self {
+        ThresholdMode::Adaptive { margin_db: DEFAULT_MARGIN_DB }
+    }
+}
 
 pub struct VoiceActivityDetector {
-    /// Energy threshold for detecting voice activity (adjust based on testing)
-    energy_threshold: f32,
+    /// How the speech threshold is chosen
+    mode: ThresholdMode,
     /// Minimum consecutive silent frames before declaring silence
     silence_frame_count: usize,
     /// Current count of consecutive silent frames
     current_silent_frames: usize,
     /// Frame size in samples
     frame_size: usize,
+    /// Energies of the most recent frames, oldest first
+    history: VecDeque<f32>,
+    /// Frames kept in `history`
+    history_frames: usize,
+    /// Frames needed in `history` before the noise floor is estimated from it
+    min_history_frames: usize,
+    /// Current noise floor estimate
+    noise_floor: f32,
 }
 
 impl VoiceActivityDetector {
+    /// Detector with a fixed energy threshold
     pub fn new(energy_threshold: f32, silence_duration_ms: u32, sample_rate: u32) -> Self {
+        Self::with_mode(
+            ThresholdMode::Fixed { threshold: energy_threshold },
+            silence_duration_ms,
+            sample_rate,
+        )
+    }
+
+    /// Detector whose threshold sits `margin_db` above the ambient noise floor
+    pub fn adaptive(margin_db: f32, silence_duration_ms: u32, sample_rate: u32) -> Self {
+        Self::with_mode(ThresholdMode::Adaptive { margin_db }, silence_duration_ms, sample_rate)
+    }
+
+    pub fn with_mode(mode: ThresholdMode, silence_duration_ms: u32, sample_rate: u32) -> Self {
         // Calculate number of frames needed for silence duration
         let samples_per_ms = sample_rate as f32 / 1000.0;
         let frame_size = 512; // Process audio in 512-sample chunks
-        let silence_frames = (silence_duration_ms as f32 * samples_per_ms / frame_size as f32) as usize;
+        let frames_for_ms = |ms: u32| (ms as f32 * samples_per_ms / frame_size as f32) as usize;
+
+        let history_frames = frames_for_ms(NOISE_HISTORY_MS).max(1);
 
         Self {
-            energy_threshold,
-            silence_frame_count: silence_frames.max(1),
+            mode,
+            silence_frame_count: frames_for_ms(silence_duration_ms).max(1),
             current_silent_frames: 0,
             frame_size,
+            history: VecDeque::with_capacity(history_frames),
+            history_frames,
+            min_history_frames: frames_for_ms(MIN_NOISE_HISTORY_MS).clamp(1, history_frames),
+            noise_floor: MIN_NOISE_FLOOR,
         }
     }
 
@@ -39,19 +100,61 @@ impl VoiceActivityDetector {
         (sum_of_squares / samples.len() as f32).sqrt()
     }
 
+    /// Energy above which a frame counts as speech
+    pub fn threshold(&self) -> f32 {
+        match self.mode {
+            ThresholdMode::Fixed { threshold } => threshold,
+            ThresholdMode::Adaptive { margin_db } => {
+                self.noise_floor * 10f32.powf(margin_db / 20.0)
+            }
+        }
+    }
+
+    /// Estimated RMS energy of the background noise, tracked in either mode
+    pub fn noise_floor(&self) -> f32 {
+        self.noise_floor
+    }
+
+    /// Add a frame's energy to the history and re-estimate the noise floor
+    fn track_noise(&mut self, energy: f32) {
+        if self.history.len() == self.history_frames {
+            self.history.pop_front();
+        }
+        self.history.push_back(energy);
+
+        if self.history.len() < self.min_history_frames {
+            return;
+        }
+
+        let mut energies: Vec<f32> = self.history.iter().copied().collect();
+        let index = ((energies.len() - 1) as f32 * NOISE_PERCENTILE) as usize;
+        let (_, percentile, _) = energies.select_nth_unstable_by(index, f32::total_cmp);
+        self.noise_floor = percentile.max(MIN_NOISE_FLOOR);
+    }
+
+    /// Learn the noise floor from audio heard before detection starts, such as the
+    /// background listener's buffer, without counting it towards silence
+    pub fn prime(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.frame_size) {
+            let energy = self.calculate_energy(frame);
+            self.track_noise(energy);
+        }
+    }
+
     /// Process audio samples and detect voice activity
     /// Returns true if voice is detected, false if silence
     pub fn is_voice_active(&self, samples: &[f32]) -> bool {
         let energy = self.calculate_energy(samples);
-        energy > self.energy_threshold
+        energy > self.threshold()
     }
 
     /// Process audio and check if silence has been sustained long enough
     /// Returns true if silence duration threshold has been reached
     pub fn process_frame(&mut self, samples: &[f32]) -> SilenceState {
-        let is_active = self.is_voice_active(samples);
+        let energy = self.calculate_energy(samples);
+        self.track_noise(energy);
 
-        if is_active {
+        if energy > self.threshold() {
             // Voice detected, reset silence counter
             self.current_silent_frames = 0;
             SilenceState::Voice
@@ -67,7 +170,7 @@ impl VoiceActivityDetector {
         }
     }
 
-    /// Reset the VAD state
+    /// Reset the VAD state, keeping the noise floor since the room hasn't changed
     pub fn reset(&mut self) {
         self.current_silent_frames = 0;
     }
@@ -92,8 +195,8 @@ pub enum SilenceState {
 
 impl Default for VoiceActivityDetector {
     fn default() -> Self {
-        // Default: 0.01 energy threshold, 1.5 seconds of silence, 16kHz sample rate
-        Self::new(0.01, 1500, 16000)
+        // Default: adaptive threshold, 1.5 seconds of silence, 16kHz sample rate
+        Self::with_mode(ThresholdMode::default(), 1500, 16000)
     }
 }
 
@@ -101,17 +204,45 @@ impl Default for VoiceActivityDetector {
 mod tests {
     use super::*;
 
+    /// Deterministic white noise with the given RMS energy
+    fn noise(rms: f32, len: usize, seed: u32) -> Vec<f32> {
+        let mut state = seed.max(1);
+        (0..len)
+            .map(|_| {
+                // xorshift32, scaled so uniform noise in [-a, a] has RMS a / sqrt(3)
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let uniform = state as f32 / u32::MAX as f32 * 2.0 - 1.0;
+                uniform * rms * 3f32.sqrt()
+            })
+            .collect()
+    }
+
+    /// Noise with a 200 Hz tone on top, `snr_db` louder than the noise
+    fn speech(noise_rms: f32, snr_db: f32, len: usize, seed: u32) -> Vec<f32> {
+        let tone_rms = noise_rms * 10f32.powf(snr_db / 20.0);
+        noise(noise_rms, len, seed)
+            .into_iter()
+            .enumerate()
+            .map(|(i, n)| {
+                let phase = 2.0 * std::f32::consts::PI * 200.0 * i as f32 / 16000.0;
+                n + tone_rms * 2f32.sqrt() * phase.sin()
+            })
+            .collect()
+    }
+
     #[test]
     fn test_silence_detection() {
-        let mut vad = VoiceActivityDetector::new(0.01, 100, 16000);
+        let vad = VoiceActivityDetector::new(0.01, 100, 16000);
 
         // Silent frame (low energy)
         let silent_samples = vec![0.001; 512];
-        assert_eq!(vad.is_voice_active(&silent_samples), false);
+        assert!(!vad.is_voice_active(&silent_samples));
 
         // Voice frame (high energy)
         let voice_samples = vec![0.1; 512];
-        assert_eq!(vad.is_voice_active(&voice_samples), true);
+        assert!(vad.is_voice_active(&voice_samples));
     }
 
     #[test]
@@ -122,11 +253,75 @@ mod tests {
         // Process frames until silence is detected
         for i in 0..10 {
             let state = vad.process_frame(&silent_samples);
-            if i < vad.silence_frame_count {
+            if i + 1 < vad.silence_frame_count {
                 assert!(state != SilenceState::SilenceDetected);
             } else {
                 assert_eq!(state, SilenceState::SilenceDetected);
             }
         }
     }
+
+    #[test]
+    fn test_adaptive_threshold_follows_noise_level() {
+        // Quiet mic, typical room and open-plan office
+        for (seed, noise_rms) in [(1, 0.001), (2, 0.01), (3, 0.08)] {
+            let mut vad = VoiceActivityDetector::adaptive(DEFAULT_MARGIN_DB, 1500, 16000);
+            vad.prime(&noise(noise_rms, 16000 * 3, seed));
+
+            let floor = vad.noise_floor();
+            assert!(
+                floor > noise_rms * 0.8 && floor < noise_rms * 1.2,
+                "floor {floor} for noise {noise_rms}"
+            );
+
+            for frame in noise(noise_rms, 512 * 20, seed + 10).chunks_exact(512) {
+                assert_ne!(vad.process_frame(frame), SilenceState::Voice, "noise {noise_rms}");
+            }
+            for frame in speech(noise_rms, 20.0, 512 * 20, seed + 20).chunks_exact(512) {
+                assert_eq!(vad.process_frame(frame), SilenceState::Voice, "noise {noise_rms}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_adaptive_detects_silence_where_fixed_does_not() {
+        let noise_rms = 0.05;
+        let mut fixed = VoiceActivityDetector::new(0.02, 500, 16000);
+        let mut adaptive = VoiceActivityDetector::adaptive(DEFAULT_MARGIN_DB, 500, 16000);
+        adaptive.prime(&noise(noise_rms, 16000 * 2, 3));
+
+        let mut audio = speech(noise_rms, 20.0, 16000, 4);
+        audio.extend(noise(noise_rms, 16000, 5));
+
+        let fixed_states: Vec<_> = audio.chunks_exact(512).map(|f| fixed.process_frame(f)).collect();
+        let adaptive_states: Vec<_> =
+            audio.chunks_exact(512).map(|f| adaptive.process_frame(f)).collect();
+
+        // Office noise is over the fixed threshold, so the speaker never stops talking
+        assert!(!fixed_states.contains(&SilenceState::SilenceDetected));
+        assert_eq!(adaptive_states.last(), Some(&SilenceState::SilenceDetected));
+    }
+
+    #[test]
+    fn test_floor_tracks_changing_noise() {
+        let mut vad = VoiceActivityDetector::adaptive(DEFAULT_MARGIN_DB, 1500, 16000);
+        vad.prime(&noise(0.002, 16000 * 5, 6));
+        assert!(vad.noise_floor() < 0.003);
+
+        // An air conditioner turns on
+        for frame in noise(0.03, 16000 * 6, 7).chunks_exact(512) {
+            vad.process_frame(frame);
+        }
+        assert!(vad.noise_floor() > 0.025);
+        assert!(!vad.is_voice_active(&noise(0.03, 512, 8)));
+    }
+
+    #[test]
+    fn test_digital_silence_keeps_minimum_floor() {
+        let mut vad = VoiceActivityDetector::adaptive(DEFAULT_MARGIN_DB, 1500, 16000);
+        vad.prime(&vec![0.0; 16000]);
+
+        assert_eq!(vad.noise_floor(), MIN_NOISE_FLOOR);
+        assert!(vad.is_voice_active(&noise(0.01, 512, 9)));
+    }
 }
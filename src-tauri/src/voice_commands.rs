@@ -1,18 +1,66 @@
-use crate::audio::AudioRecorder;
-use crate::vad::{SilenceState, VoiceActivityDetector};
-use crate::whisper::WhisperTranscriber;
+use crate::audio::{AudioFormat, AudioRecorder};
+use crate::audio_source::AudioSource;
+use crate::command_map::{CommandMap, CommandMapping};
+use crate::decode;
+use crate::grammar::CommandGrammar;
+use crate::recognizer::SpeechRecognizer;
+use crate::streaming::{PartialTranscript, StabilityTracker};
+use crate::downmix::{downmix, DownmixMode};
+use crate::vad::{SilenceState, ThresholdMode, VoiceActivityDetector, NOISE_HISTORY_MS};
+use crate::whisper::{
+    vocabulary_prompt, FilteredText, Interrupted, Transcription, TranscriptionOptions,
+    WhisperTranscriber,
+};
+use crate::worker::Priority;
 use anyhow::{Context, Result};
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::task::JoinHandle;
+
+/// Length of the rolling window checked for wake words
+const WAKE_WINDOW_MS: u64 = 2000;
+
+/// How often the background listener re-checks the rolling window
+const WAKE_HOP_MS: u64 = 500;
+
+/// First pause after a failed wake word check, doubled for each failure in a row
+const WAKE_ERROR_BACKOFF_MS: u64 = 1000;
+
+/// Longest pause between wake word checks while they keep failing
+const MAX_WAKE_ERROR_BACKOFF_MS: u64 = 30_000;
+
+/// Decoding threads for a separate wake word model
+const WAKE_MODEL_WORKERS: usize = 1;
+
+/// Audio kept from before a recording starts so the first syllable isn't clipped
+const DEFAULT_PRE_ROLL_MS: u64 = 500;
+
+/// Commands transcribed with less confidence than this are not acted on
+const DEFAULT_MIN_CONFIDENCE: f32 = 0.4;
+
+/// How often a command recording is re-decoded for partial transcripts
+const PARTIAL_INTERVAL_MS: u64 = 400;
+
+/// Most recent audio re-decoded for each partial transcript
+const PARTIAL_WINDOW_MS: u64 = 10_000;
+
+/// Recordings shorter than this are not worth a partial decode
+const MIN_PARTIAL_MS: u64 = 300;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VoiceCommand {
     pub text: String,
     pub confidence: f32,
     pub timestamp: u64,
+    /// Language the command was spoken in, when known
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Text the hallucination filter dropped, and why
+    #[serde(default)]
+    pub filtered: Vec<FilteredText>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,33 +74,205 @@ pub struct RecordingStatus {
 pub struct ListeningEvent {
     pub event_type: String,
     pub message: String,
+    pub command: Option<VoiceCommand>,
+}
+
+impl ListeningEvent {
+    fn new(event_type: &str, message: impl Into<String>) -> Self {
+        Self {
+            event_type: event_type.to_string(),
+            message: message.into(),
+            command: None,
+        }
+    }
+}
+
+/// Progress of a model switch: "loading", then "loaded" or "failed"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelSwitchEvent {
+    pub status: String,
+    pub model_path: String,
+    pub message: String,
+}
+
+impl ModelSwitchEvent {
+    pub fn new(status: &str, model_path: &str, message: impl Into<String>) -> Self {
+        Self {
+            status: status.to_string(),
+            model_path: model_path.to_string(),
+            message: message.into(),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct VoiceCommandHandler {
-    recorder: Arc<Mutex<AudioRecorder>>,
-    transcriber: Arc<WhisperTranscriber>,
+    source: Arc<Mutex<dyn AudioSource>>,
+    /// Set when `source` is the microphone, for device selection
+    recorder: Option<Arc<Mutex<AudioRecorder>>>,
+    /// Transcribes commands and files
+    transcriber: Arc<dyn SpeechRecognizer>,
+    /// Smaller local model for the always-on wake word check, None to use `transcriber`
+    wake_transcriber: Arc<Mutex<Option<Arc<WhisperTranscriber>>>>,
+    options: Arc<Mutex<TranscriptionOptions>>,
+    min_confidence: Arc<Mutex<f32>>,
+    command_map: Arc<Mutex<CommandMap>>,
+    /// Constrain recorded commands to wake words and command phrases
+    command_mode: Arc<AtomicBool>,
+    /// Project names and jargon to bias decoding towards
+    custom_vocabulary: Arc<Mutex<Vec<String>>>,
+    /// Emit partial transcripts while a command is being recorded
+    streaming: Arc<AtomicBool>,
     is_initialized: Arc<Mutex<bool>>,
     is_listening: Arc<AtomicBool>,
-    sample_rate: u32,
+    /// Bumped by every start, so a listener that was stopped and replaced can tell
+    listener_generation: Arc<AtomicU64>,
+    listener_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    pre_roll_ms: Arc<AtomicU64>,
+    /// How the VAD decides a recorded command has ended
+    vad_mode: Arc<Mutex<ThresholdMode>>,
     wake_words: Vec<String>,
 }
 
 impl VoiceCommandHandler {
     pub fn new(model_path: PathBuf) -> Self {
+        Self::with_recognizer(Arc::new(WhisperTranscriber::new(model_path)))
+    }
+
+    /// Build a handler that listens on the microphone and transcribes through any backend
+    pub fn with_recognizer(recognizer: Arc<dyn SpeechRecognizer>) -> Self {
+        let recorder = Arc::new(Mutex::new(AudioRecorder::new()));
+        let mut handler = Self::with_backend(recognizer, recorder.clone());
+        handler.recorder = Some(recorder);
+        handler
+    }
+
+    /// Build a handler that reads audio from any source instead of the microphone,
+    /// transcribing through any backend
+    pub fn with_backend(
+        recognizer: Arc<dyn SpeechRecognizer>,
+        source: Arc<Mutex<dyn AudioSource>>,
+    ) -> Self {
         Self {
-            recorder: Arc::new(Mutex::new(AudioRecorder::new())),
-            transcriber: Arc::new(WhisperTranscriber::new(model_path)),
+            source,
+            recorder: None,
+            transcriber: recognizer,
+            wake_transcriber: Arc::new(Mutex::new(None)),
+            options: Arc::new(Mutex::new(TranscriptionOptions::default())),
+            min_confidence: Arc::new(Mutex::new(DEFAULT_MIN_CONFIDENCE)),
+            command_map: Arc::new(Mutex::new(CommandMap::default())),
+            command_mode: Arc::new(AtomicBool::new(false)),
+            custom_vocabulary: Arc::new(Mutex::new(Vec::new())),
+            streaming: Arc::new(AtomicBool::new(true)),
             is_initialized: Arc::new(Mutex::new(false)),
             is_listening: Arc::new(AtomicBool::new(false)),
-            sample_rate: 16000, // Whisper expects 16kHz
+            listener_generation: Arc::new(AtomicU64::new(0)),
+            listener_task: Arc::new(Mutex::new(None)),
+            pre_roll_ms: Arc::new(AtomicU64::new(DEFAULT_PRE_ROLL_MS)),
+            vad_mode: Arc::new(Mutex::new(ThresholdMode::default())),
             wake_words: vec!["kiku".to_string(), "computer".to_string()],
         }
     }
 
-    pub fn set_audio_device(&self, device_name: Option<String>) {
-        let mut recorder = self.recorder.lock();
-        recorder.set_device(device_name);
+    pub fn set_audio_device(&self, device_name: Option<String>) -> Result<()> {
+        let recorder = self.recorder.as_ref().context("Audio source has no selectable devices")?;
+        recorder.lock().set_device(device_name)
+    }
+
+    pub fn set_downmix_mode(&self, device_name: Option<String>, mode: DownmixMode) -> Result<()> {
+        let recorder = self.recorder.as_ref().context("Audio source has no selectable devices")?;
+        recorder.lock().set_downmix_mode(device_name, mode);
+        Ok(())
+    }
+
+    /// How much audio from before a recording starts is kept, when the source is already open
+    pub fn set_pre_roll_ms(&self, pre_roll_ms: u64) {
+        self.pre_roll_ms.store(pre_roll_ms, Ordering::Relaxed);
+    }
+
+    /// Speech threshold used to detect the end of later commands
+    pub fn set_vad_mode(&self, mode: ThresholdMode) {
+        *self.vad_mode.lock() = mode;
+    }
+
+    /// Decoding parameters used by every later transcription
+    pub fn set_transcription_options(&self, options: TranscriptionOptions) {
+        *self.options.lock() = options;
+    }
+
+    pub fn transcription_options(&self) -> TranscriptionOptions {
+        self.options.lock().clone()
+    }
+
+    /// Confidence below which `process_command` ignores a transcription
+    pub fn set_min_confidence(&self, min_confidence: f32) {
+        *self.min_confidence.lock() = min_confidence.clamp(0.0, 1.0);
+    }
+
+    /// Replace the command mappings for a language, or remove them when `mappings` is empty
+    pub fn set_command_mappings(&self, language: &str, mappings: Vec<CommandMapping>) {
+        self.command_map.lock().set_language(language, mappings);
+    }
+
+    pub fn command_mappings(&self, language: &str) -> Vec<CommandMapping> {
+        self.command_map
+            .lock()
+            .language(language)
+            .map(<[CommandMapping]>::to_vec)
+            .unwrap_or_default()
+    }
+
+    /// Words Whisper should prefer, most important first
+    pub fn set_custom_vocabulary(&self, vocabulary: Vec<String>) {
+        *self.custom_vocabulary.lock() = vocabulary;
+    }
+
+    pub fn custom_vocabulary(&self) -> Vec<String> {
+        self.custom_vocabulary.lock().clone()
+    }
+
+    /// Options as passed to Whisper for commands and files, with the vocabulary prompt filled in
+    /// when enabled
+    fn decoding_options(&self, options: TranscriptionOptions) -> TranscriptionOptions {
+        if !options.vocabulary_biasing {
+            return options;
+        }
+
+        // Custom vocabulary first since it's what Whisper gets wrong most
+        let mut vocabulary = self.custom_vocabulary();
+        vocabulary.extend(self.wake_words.iter().cloned());
+        vocabulary.extend(self.command_map.lock().phrases());
+
+        TranscriptionOptions {
+            initial_prompt: vocabulary_prompt(options.initial_prompt.as_deref(), &vocabulary),
+            ..options
+        }
+    }
+
+    /// Whether recorded commands are decoded against the command grammar, off by default since
+    /// it forces any speech into the nearest command. File transcription is always free dictation
+    pub fn set_command_mode(&self, enabled: bool) {
+        self.command_mode.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Whether commands recorded with VAD are re-decoded while recording for partial transcripts
+    pub fn set_streaming(&self, enabled: bool) {
+        self.streaming.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Grammar of every wake word and command phrase, in any language
+    pub fn command_grammar_gbnf(&self) -> String {
+        CommandGrammar::new(self.wake_words.clone(), self.command_map.lock().phrases()).to_gbnf()
+    }
+
+    fn command_grammar(&self) -> Option<CommandGrammar> {
+        if !self.command_mode.load(Ordering::Relaxed) {
+            return None;
+        }
+        Some(CommandGrammar::new(
+            self.wake_words.clone(),
+            self.command_map.lock().phrases(),
+        ))
     }
 
     pub fn initialize(&self) -> Result<()> {
@@ -61,13 +281,69 @@ impl VoiceCommandHandler {
         }
 
         self.transcriber
-            .load_model()
-            .context("Failed to load Whisper model")?;
+            .load()
+            .context("Failed to load speech recognizer")?;
         *self.is_initialized.lock() = true;
 
         Ok(())
     }
 
+    /// Replace the command model without stopping a running listener
+    pub fn switch_model(&self, model_path: PathBuf) -> Result<()> {
+        if !*self.is_initialized.lock() {
+            return Err(anyhow::anyhow!(
+                "Voice command handler not initialized. Call initialize() first."
+            ));
+        }
+
+        self.transcriber.switch_model(model_path)
+    }
+
+    /// Load a separate model for wake word detection, or go back to the command model with None
+    ///
+    /// The new model is loaded before it replaces the old one, so a running listener keeps going.
+    pub fn set_wake_model(&self, model_path: Option<PathBuf>) -> Result<()> {
+        let transcriber = match model_path {
+            Some(model_path) => {
+                // Checks run one at a time, so one worker is enough and leaves the CPU to commands
+                let transcriber = WhisperTranscriber::with_workers(model_path, WAKE_MODEL_WORKERS);
+                transcriber
+                    .load_model()
+                    .context("Failed to load wake word model")?;
+                Some(Arc::new(transcriber))
+            }
+            None => None,
+        };
+
+        // The old model's worker finishes any check in hand, then exits
+        let replaced = std::mem::replace(&mut *self.wake_transcriber.lock(), transcriber);
+        if let Some(replaced) = replaced {
+            replaced.shutdown();
+        }
+        Ok(())
+    }
+
+    /// Path of the separate wake word model, if one is loaded
+    pub fn wake_model_path(&self) -> Option<PathBuf> {
+        self.wake_transcriber
+            .lock()
+            .as_ref()
+            .map(|transcriber| transcriber.model_path())
+    }
+
+    /// Whether `model_path` is loaded as the command or the wake word model
+    pub fn uses_model(&self, model_path: &std::path::Path) -> bool {
+        self.transcriber.model_path().as_deref() == Some(model_path)
+            || self.wake_model_path().as_deref() == Some(model_path)
+    }
+
+    fn wake_word_transcriber(&self) -> Arc<dyn SpeechRecognizer> {
+        match self.wake_transcriber.lock().clone() {
+            Some(transcriber) => transcriber,
+            None => Arc::clone(&self.transcriber),
+        }
+    }
+
     pub fn start_recording(&self) -> Result<()> {
         if !*self.is_initialized.lock() {
             return Err(anyhow::anyhow!(
@@ -75,9 +351,9 @@ impl VoiceCommandHandler {
             ));
         }
 
-        self.recorder
+        self.source
             .lock()
-            .start_recording()
+            .start(self.pre_roll_ms.load(Ordering::Relaxed))
             .context("Failed to start recording")?;
 
         Ok(())
@@ -85,47 +361,39 @@ impl VoiceCommandHandler {
 
     pub async fn stop_recording_and_transcribe(&self) -> Result<VoiceCommand> {
         // Stop recording and get samples - drop the lock immediately
-        let (samples, original_sample_rate) = {
-            let recorder = self.recorder.lock();
-            let samples = recorder.stop_recording();
-            let original_sample_rate = 48000; // You might want to detect this dynamically
-            (samples, original_sample_rate)
-        };
-
-        if samples.is_empty() {
-            return Err(anyhow::anyhow!("No audio data recorded"));
-        }
+        let recorded = self.source.lock().stop();
+        let recorded = recorded
+            .filter(|audio| !audio.samples.is_empty())
+            .context("No audio data recorded")?;
 
         // Convert to mono 16kHz as required by Whisper
         let resampled = {
-            let recorder = self.recorder.lock();
-            recorder.convert_to_16khz_mono(&samples, original_sample_rate)
+            let source = self.source.lock();
+            source.convert_to_16khz_mono(&recorded.samples, &recorded.format)
         };
 
-        // Clone transcriber Arc for the blocking task
-        let transcriber = Arc::clone(&self.transcriber);
-
-        // Transcribe the audio in a blocking task to avoid blocking the async runtime
-        let text = tokio::task::spawn_blocking(move || {
-            transcriber.transcribe(&resampled)
-        })
-        .await
-        .context("Failed to spawn transcription task")?
+        let options = self.decoding_options(self.transcription_options());
+        let transcription = match self.command_grammar() {
+            Some(grammar) => self.transcriber.transcribe_command(resampled, options, grammar).await,
+            None => self.transcriber.transcribe(resampled, options, Priority::Command).await,
+        }
         .context("Failed to transcribe audio")?;
 
         Ok(VoiceCommand {
-            text,
-            confidence: 1.0, // Whisper doesn't provide confidence scores
+            text: transcription.text,
+            confidence: transcription.confidence,
             timestamp: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            language: Some(transcription.language),
+            filtered: transcription.filtered,
         })
     }
 
     pub fn get_recording_status(&self) -> RecordingStatus {
         RecordingStatus {
-            is_recording: self.recorder.lock().is_recording(),
+            is_recording: self.source.lock().is_active(),
             is_listening: self.is_listening.load(Ordering::Relaxed),
             duration_ms: 0, // Could track this if needed
         }
@@ -136,29 +404,172 @@ impl VoiceCommandHandler {
     }
 
     /// Start background listening for wake words
-    pub fn start_background_listening(&self) -> Result<()> {
+    ///
+    /// Spawns a long-running task that captures continuously, checks a rolling
+    /// window for wake words and records a command with VAD whenever one fires.
+    /// Must be called from within a tokio runtime.
+    pub fn start_background_listening<F, P>(&self, on_event: F, on_partial: P) -> Result<()>
+    where
+        F: Fn(ListeningEvent) + Send + Sync + 'static,
+        P: Fn(PartialTranscript) + Send + Sync + 'static,
+    {
         if !*self.is_initialized.lock() {
             return Err(anyhow::anyhow!(
                 "Voice command handler not initialized. Call initialize() first."
             ));
         }
 
-        if self.is_listening.load(Ordering::Relaxed) {
+        // Held while starting so a previous listener's cleanup can't interleave with it
+        let mut listener_task = self.listener_task.lock();
+        if self.is_listening.swap(true, Ordering::SeqCst) {
             return Err(anyhow::anyhow!("Already listening for wake words"));
         }
+        let generation = self.listener_generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        // A previous listener may still be winding down after a stop
+        if let Some(task) = listener_task.take() {
+            task.abort();
+        }
+
+        let handler = self.clone();
+        let task = tokio::spawn(async move {
+            if let Err(e) = handler
+                .run_background_listener(generation, &on_event, &on_partial)
+                .await
+            {
+                on_event(ListeningEvent::new("error", e.to_string()));
+            }
 
-        self.is_listening.store(true, Ordering::Relaxed);
+            // A listener started since owns the flag and the source now, leave them to it
+            let _listener_task = handler.listener_task.lock();
+            if handler.listener_generation.load(Ordering::SeqCst) != generation {
+                return;
+            }
+            handler.is_listening.store(false, Ordering::SeqCst);
+            let source = handler.source.lock();
+            if source.is_active() {
+                source.stop();
+            }
+            source.close();
+            drop(source);
+
+            on_event(ListeningEvent::new("stopped", "Background listening stopped"));
+        });
+
+        *listener_task = Some(task);
         Ok(())
     }
 
     /// Stop background listening
     pub fn stop_background_listening(&self) -> Result<()> {
-        self.is_listening.store(false, Ordering::Relaxed);
+        self.is_listening.store(false, Ordering::SeqCst);
+
+        // Stop recording if currently recording and release the device
+        let source = self.source.lock();
+        if source.is_active() {
+            source.stop();
+        }
+        source.close();
+
+        Ok(())
+    }
+
+    /// Whether the listener started as `generation` should keep going
+    fn listening_as(&self, generation: u64) -> bool {
+        self.is_listening.load(Ordering::SeqCst)
+            && self.listener_generation.load(Ordering::SeqCst) == generation
+    }
+
+    /// Body of the background listener task, runs until `is_listening` is cleared
+    /// or another listener is started
+    async fn run_background_listener<F, P>(
+        &self,
+        generation: u64,
+        on_event: &F,
+        on_partial: &P,
+    ) -> Result<()>
+    where
+        F: Fn(ListeningEvent) + Send + Sync,
+        P: Fn(PartialTranscript) + Send + Sync,
+    {
+        let hop_duration = std::time::Duration::from_millis(WAKE_HOP_MS);
+        let mut backoff_ms = 0;
+
+        on_event(ListeningEvent::new("listening", "Listening for wake words"));
+
+        // Keep capture running between commands so recordings can include pre-roll
+        self.source.lock().open().context("Failed to open audio source")?;
 
-        // Stop recording if currently recording
-        let recorder = self.recorder.lock();
-        if recorder.is_recording() {
-            recorder.stop_recording();
+        while self.listening_as(generation) {
+            tokio::time::sleep(hop_duration).await;
+
+            if !self.listening_as(generation) {
+                break;
+            }
+
+            let (window, format) = {
+                let source = self.source.lock();
+                let format = source.format().context("Recording format unknown")?;
+                (source.latest(format.samples_for_ms(WAKE_WINDOW_MS)), format)
+            };
+
+            if window.len() < format.samples_for_ms(WAKE_HOP_MS) {
+                continue;
+            }
+
+            let resampled = self
+                .source
+                .lock()
+                .convert_to_16khz_mono(&window, &format);
+
+            let wake_word = match self.detect_wake_word(resampled).await {
+                Ok(wake_word) => {
+                    backoff_ms = 0;
+                    wake_word
+                }
+                // A window that ran past the timeout is skipped, the next hop checks again
+                Err(e) if e.downcast_ref::<Interrupted>().is_some() => None,
+                // A failed check, such as a transcription server being down, is retried after
+                // a pause rather than ending hands-free listening, the backoff keeps the reports
+                // of a lasting outage sparse
+                Err(e) => {
+                    on_event(ListeningEvent::new("error", format!("{:#}", e)));
+                    backoff_ms = (backoff_ms * 2)
+                        .clamp(WAKE_ERROR_BACKOFF_MS, MAX_WAKE_ERROR_BACKOFF_MS);
+                    tokio::time::sleep(std::time::Duration::from_millis(backoff_ms)).await;
+                    continue;
+                }
+            };
+
+            let Some(wake_word) = wake_word else {
+                continue;
+            };
+
+            if !self.listening_as(generation) {
+                break;
+            }
+
+            on_event(ListeningEvent::new(
+                "wake_word_detected",
+                format!("Wake word detected: {}", wake_word),
+            ));
+
+            match self.record_command_with_vad(on_partial).await {
+                Ok(command) => {
+                    let mut event = ListeningEvent::new("command", command.text.clone());
+                    event.command = Some(command);
+                    on_event(event);
+                }
+                // Listening was stopped mid-command, which isn't an error
+                Err(_) if !self.listening_as(generation) => break,
+                Err(e) => match e.downcast_ref::<Interrupted>() {
+                    Some(Interrupted::Cancelled) => {
+                        on_event(ListeningEvent::new("cancelled", "Transcription cancelled"))
+                    }
+                    Some(interrupted) => on_event(ListeningEvent::new("error", interrupted.to_string())),
+                    None => on_event(ListeningEvent::new("error", e.to_string())),
+                },
+            }
         }
 
         Ok(())
@@ -166,15 +577,20 @@ impl VoiceCommandHandler {
 
     /// Process a chunk of audio for wake word detection
     /// Returns Some(wake_word) if detected, None otherwise
-    fn detect_wake_word(&self, samples: &[f32]) -> Result<Option<String>> {
+    async fn detect_wake_word(&self, samples: Vec<f32>) -> Result<Option<String>> {
         if samples.len() < 1000 {
             // Not enough audio to transcribe
             return Ok(None);
         }
 
-        // Transcribe the chunk
-        let text = self.transcriber.transcribe(samples)
-            .context("Failed to transcribe audio chunk")?;
+        // Transcribe the chunk without the vocabulary prompt, which lists the wake words and
+        // would push Whisper towards hearing them in noise
+        let options = self.transcription_options();
+        let text = self.wake_word_transcriber()
+            .transcribe(samples, options, Priority::WakeWord)
+            .await
+            .context("Failed to transcribe audio chunk")?
+            .text;
 
         let text_lower = text.to_lowercase();
 
@@ -189,18 +605,53 @@ impl VoiceCommandHandler {
     }
 
     /// Record a command after wake word detected, auto-stopping on silence
-    pub async fn record_command_with_vad(&self) -> Result<VoiceCommand> {
-        // Start recording
-        self.recorder.lock().start_recording()
-            .context("Failed to start recording")?;
+    /// Streams partial transcripts through `on_partial` while recording when streaming is enabled
+    pub async fn record_command_with_vad<P>(&self, on_partial: &P) -> Result<VoiceCommand>
+    where
+        P: Fn(PartialTranscript) + Send + Sync,
+    {
+        // Start recording, with pre-roll if the background listener already has the source open
+        {
+            let source = self.source.lock();
+            if !source.is_active() {
+                source.start(self.pre_roll_ms.load(Ordering::Relaxed))
+                    .context("Failed to start recording")?;
+            }
+        }
 
-        // Create VAD with 1.5 second silence threshold
-        let mut vad = VoiceActivityDetector::new(0.02, 1500, 16000);
+        let format = self.source.lock().format().context("Recording format unknown")?;
+
+        // Create VAD with 1.5 second silence threshold, run on the mono mix
+        let mut vad =
+            VoiceActivityDetector::with_mode(*self.vad_mode.lock(), 1500, format.sample_rate);
+        let channels = format.channels.max(1);
+        let downmix_mode = self.source.lock().downmix_mode();
+
+        // Learn the noise floor from what the background listener heard before the command,
+        // leaving out the window the wake word was just spoken in
+        {
+            let source = self.source.lock();
+            let history =
+                source.latest(format.samples_for_ms(NOISE_HISTORY_MS as u64 + WAKE_WINDOW_MS));
+            let before_wake = history
+                .len()
+                .saturating_sub(format.samples_for_ms(WAKE_WINDOW_MS));
+            vad.prime(&downmix(&history[..before_wake], channels, downmix_mode));
+        }
+        // Interleaved samples of the recording already run through the VAD
+        let mut vad_position = 0;
+        let vad_block = vad.frame_size() * channels as usize;
 
         let max_recording_duration = std::time::Duration::from_secs(10);
         let start_time = std::time::Instant::now();
         let chunk_duration = std::time::Duration::from_millis(100);
 
+        let streaming = self.streaming.load(Ordering::Relaxed);
+        let partial_interval = std::time::Duration::from_millis(PARTIAL_INTERVAL_MS);
+        let mut last_partial = std::time::Instant::now();
+        let mut partial_task: Option<JoinHandle<Result<Transcription>>> = None;
+        let mut tracker = StabilityTracker::new();
+
         // Record until silence detected or max duration reached
         loop {
             // Use tokio sleep instead of std::thread::sleep to not block
@@ -211,93 +662,418 @@ impl VoiceCommandHandler {
                 break;
             }
 
+            // Stopped from outside, such as by stopping background listening
+            if !self.source.lock().is_active() {
+                if let Some(task) = partial_task.take() {
+                    task.abort();
+                }
+                return Err(Interrupted::Cancelled.into());
+            }
+
             // Get current samples
-            let samples = self.recorder.lock().get_current_samples();
+            let samples = self.source.lock().read();
 
-            // Process with VAD
-            if samples.len() >= vad.frame_size() {
-                let frame_start = samples.len().saturating_sub(vad.frame_size());
-                let frame = &samples[frame_start..];
+            // Process every new frame with VAD, so silence is counted over all of the audio
+            let new = &samples[vad_position.min(samples.len())..];
+            let new = &new[..new.len() / vad_block * vad_block];
+            vad_position += new.len();
+            let silence = downmix(new, channels, downmix_mode)
+                .chunks_exact(vad.frame_size())
+                .map(|frame| vad.process_frame(frame))
+                .any(|state| state == SilenceState::SilenceDetected);
 
-                let state = vad.process_frame(frame);
+            if silence {
+                // Silence detected, stop recording
+                break;
+            }
+
+            if !streaming {
+                continue;
+            }
 
-                if state == SilenceState::SilenceDetected {
-                    // Silence detected, stop recording
-                    break;
+            // Emit the previous partial decode once it's done
+            if let Some(task) = partial_task.as_mut() {
+                if task.is_finished() {
+                    if let Ok(Ok(partial)) = task.await {
+                        on_partial(tracker.update(&partial.text));
+                    }
+                    partial_task = None;
                 }
             }
-        }
 
-        // Stop recording and transcribe - drop the lock immediately
-        let (samples, original_sample_rate) = {
-            let recorder = self.recorder.lock();
-            let samples = recorder.stop_recording();
-            let original_sample_rate = 48000;
-            (samples, original_sample_rate)
-        };
+            // Re-decode the latest window, at most one decode in flight so they never queue up
+            if partial_task.is_none()
+                && last_partial.elapsed() >= partial_interval
+                && samples.len() >= format.samples_for_ms(MIN_PARTIAL_MS)
+            {
+                last_partial = std::time::Instant::now();
+                let window_start = samples
+                    .len()
+                    .saturating_sub(format.samples_for_ms(PARTIAL_WINDOW_MS));
+                partial_task = Some(self.spawn_partial_decode(&samples[window_start..], &format));
+            }
+        }
 
-        if samples.is_empty() {
-            return Err(anyhow::anyhow!("No audio data recorded"));
+        // A partial still in flight is superseded by the final decode
+        if let Some(task) = partial_task.take() {
+            task.abort();
         }
 
+        // Stop recording and transcribe - drop the lock immediately
+        let recorded = self.source.lock().stop();
+        let recorded = recorded
+            .filter(|audio| !audio.samples.is_empty())
+            .context("No audio data recorded")?;
+
         // Convert to 16kHz mono
         let resampled = {
-            let recorder = self.recorder.lock();
-            recorder.convert_to_16khz_mono(&samples, original_sample_rate)
+            let source = self.source.lock();
+            source.convert_to_16khz_mono(&recorded.samples, &recorded.format)
         };
 
-        // Clone transcriber Arc for the blocking task
+        let options = self.decoding_options(self.transcription_options());
+        let transcription = match self.command_grammar() {
+            Some(grammar) => self.transcriber.transcribe_command(resampled, options, grammar).await,
+            None => self.transcriber.transcribe(resampled, options, Priority::Command).await,
+        }
+        .context("Failed to transcribe audio")?;
+
+        if streaming {
+            on_partial(tracker.finish(&transcription.text));
+        }
+
+        Ok(VoiceCommand {
+            text: transcription.text,
+            confidence: transcription.confidence,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            language: Some(transcription.language),
+            filtered: transcription.filtered,
+        })
+    }
+
+    /// Decode part of an ongoing recording in the background
+    /// Partials are free dictation even in command mode, since the grammar would force a whole command early
+    fn spawn_partial_decode(
+        &self,
+        samples: &[f32],
+        format: &AudioFormat,
+    ) -> JoinHandle<Result<Transcription>> {
+        let resampled = self.source.lock().convert_to_16khz_mono(samples, format);
         let transcriber = Arc::clone(&self.transcriber);
+        let options = TranscriptionOptions {
+            single_segment: true,
+            word_timestamps: false,
+            ..self.decoding_options(self.transcription_options())
+        };
 
-        // Transcribe the audio in a blocking task to avoid blocking the async runtime
-        let text = tokio::task::spawn_blocking(move || {
-            transcriber.transcribe(&resampled)
+        tokio::spawn(async move {
+            transcriber
+                .transcribe(resampled, options, Priority::Command)
+                .await
         })
-        .await
-        .context("Failed to spawn transcription task")?
-        .context("Failed to transcribe audio")?;
+    }
+
+    /// Transcribe an audio file (WAV, FLAC, OGG Vorbis or MP3)
+    /// Uses the handler's transcription options unless `options` overrides them
+    pub async fn transcribe_file(
+        &self,
+        path: PathBuf,
+        options: Option<TranscriptionOptions>,
+    ) -> Result<VoiceCommand> {
+        let transcription = self.transcribe_file_detailed(path, options).await?;
 
         Ok(VoiceCommand {
-            text,
-            confidence: 1.0,
+            text: transcription.text,
+            confidence: transcription.confidence,
             timestamp: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            language: Some(transcription.language),
+            filtered: transcription.filtered,
         })
     }
 
+    /// Transcribe an audio file keeping segment timings, and word timings if the options ask for them
+    pub async fn transcribe_file_detailed(
+        &self,
+        path: PathBuf,
+        options: Option<TranscriptionOptions>,
+    ) -> Result<Transcription> {
+        if !*self.is_initialized.lock() {
+            return Err(anyhow::anyhow!(
+                "Voice command handler not initialized. Call initialize() first."
+            ));
+        }
+
+        let options = options.unwrap_or_else(|| self.transcription_options());
+        let options = self.decoding_options(options);
+
+        // Decoding the file is CPU-bound, so keep it off the async runtime
+        let samples = tokio::task::spawn_blocking(move || decode::load_for_whisper(&path))
+            .await
+            .context("Failed to spawn decoding task")??;
+        if samples.is_empty() {
+            return Err(anyhow::anyhow!("Audio file contains no samples"));
+        }
+
+        self.transcriber
+            .transcribe(samples, options, Priority::File)
+            .await
+            .context("Failed to transcribe audio file")
+    }
+
+    /// Stop command and file transcriptions in progress, returning how many were stopped
+    /// Wake word checks carry on
+    pub fn cancel_transcription(&self) -> usize {
+        self.transcriber.cancel()
+    }
+
     pub fn is_initialized(&self) -> bool {
         *self.is_initialized.lock()
     }
 
     pub fn process_command(&self, command: &VoiceCommand) -> Option<String> {
-        let text = command.text.to_lowercase();
+        // Don't trigger workflows on text Whisper wasn't sure about
+        if command.confidence < *self.min_confidence.lock() {
+            return None;
+        }
 
-        // Define your command mappings here
-        // This is where you can trigger workflows based on voice commands
+        // Command mappings are per language, see command_map.rs for the defaults
+        self.command_map
+            .lock()
+            .match_command(&command.text, command.language.as_deref())
+    }
+}
 
-        if text.contains("hello") || text.contains("hi") {
-            return Some("greeting".to_string());
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_source::{FileSource, Playback};
+    use crate::recognizer::MockRecognizer;
+    use cpal::SampleFormat;
+    use std::time::{Duration, Instant};
 
-        if text.contains("start") || text.contains("begin") {
-            return Some("start_workflow".to_string());
-        }
+    /// A second of silence played back in real time
+    fn silence() -> FileSource {
+        let format = AudioFormat {
+            sample_rate: 16000,
+            channels: 1,
+            sample_format: SampleFormat::F32,
+        };
+        FileSource::from_samples(vec![0.0; 16000], format, Playback::RealTime)
+    }
 
-        if text.contains("stop") || text.contains("end") {
-            return Some("stop_workflow".to_string());
-        }
+    /// A handler listening to `source`, collecting its listening events
+    fn listening_handler(
+        recognizer: Arc<dyn SpeechRecognizer>,
+        source: FileSource,
+    ) -> (VoiceCommandHandler, Arc<Mutex<Vec<ListeningEvent>>>) {
+        let handler = VoiceCommandHandler::with_backend(recognizer, Arc::new(Mutex::new(source)));
+        handler.initialize().unwrap();
+        // Partial decodes would take responses meant for the wake word and command
+        handler.set_streaming(false);
 
-        if text.contains("status") || text.contains("report") {
-            return Some("status_check".to_string());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        handler
+            .start_background_listening(move |event| sink.lock().push(event), |_| {})
+            .unwrap();
+        (handler, events)
+    }
+
+    async fn wait_for_event(events: &Mutex<Vec<ListeningEvent>>, event_type: &str) {
+        let deadline = Instant::now() + Duration::from_secs(15);
+        while !events.lock().iter().any(|e| e.event_type == event_type) {
+            assert!(Instant::now() < deadline, "no {} event", event_type);
+            tokio::time::sleep(Duration::from_millis(20)).await;
         }
+    }
 
-        if text.contains("help") {
-            return Some("show_help".to_string());
+    #[tokio::test]
+    async fn test_commands_through_recognizer() {
+        let recognizer = Arc::new(MockRecognizer::new(&["[BLANK_AUDIO]", "Start the workflow."]));
+        let format = AudioFormat {
+            sample_rate: 16000,
+            channels: 1,
+            sample_format: SampleFormat::F32,
+        };
+        let source = FileSource::from_samples(vec![0.1; 16000], format, Playback::Instant);
+        let handler = VoiceCommandHandler::with_backend(
+            Arc::clone(&recognizer) as Arc<dyn SpeechRecognizer>,
+            Arc::new(Mutex::new(source)),
+        );
+        handler.initialize().unwrap();
+
+        // Whisper's placeholder for silence is filtered out rather than matched
+        handler.start_recording().unwrap();
+        let command = handler.stop_recording_and_transcribe().await.unwrap();
+        assert_eq!(command.text, "");
+        assert_eq!(command.filtered[0].text, "[BLANK_AUDIO]");
+        assert_eq!(handler.process_command(&command), None);
+
+        handler.start_recording().unwrap();
+        let command = handler.stop_recording_and_transcribe().await.unwrap();
+        assert_eq!(handler.process_command(&command).as_deref(), Some("start_workflow"));
+
+        assert_eq!(
+            *recognizer.calls.lock(),
+            vec![(16000, Priority::Command), (16000, Priority::Command)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_listener_survives_failed_wake_check() {
+        let recognizer = Arc::new(MockRecognizer::with_failures(&[
+            Err("Transcription server returned HTTP 503"),
+            Ok("Kiku"),
+            Ok("Start the workflow."),
+        ]));
+        let (handler, events) = listening_handler(recognizer, silence());
+
+        wait_for_event(&events, "command").await;
+        handler.stop_background_listening().unwrap();
+
+        let events = events.lock();
+        let types: Vec<_> = events.iter().map(|e| e.event_type.as_str()).collect();
+        assert_eq!(types[..4], ["listening", "error", "wake_word_detected", "command"]);
+        assert!(events[1].message.contains("HTTP 503"));
+        assert_eq!(events[3].message, "Start the workflow.");
+    }
+
+    #[tokio::test]
+    async fn test_stop_while_recording_is_not_an_error() {
+        let recognizer = Arc::new(MockRecognizer::new(&["Kiku"]));
+        let (handler, events) = listening_handler(recognizer, silence());
+
+        wait_for_event(&events, "wake_word_detected").await;
+        handler.stop_background_listening().unwrap();
+        wait_for_event(&events, "stopped").await;
+
+        let types: Vec<_> = events.lock().iter().map(|e| e.event_type.clone()).collect();
+        assert_eq!(types, ["listening", "wake_word_detected", "stopped"]);
+    }
+
+    #[tokio::test]
+    async fn test_restart_right_after_stop() {
+        let recognizer = Arc::new(MockRecognizer::new(&["Kiku", "Start the workflow."]));
+        let (handler, first) = listening_handler(recognizer, silence());
+        handler.stop_background_listening().unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        handler
+            .start_background_listening(move |event| sink.lock().push(event), |_| {})
+            .unwrap();
+
+        // The first listener winding down leaves the second one and its source running
+        wait_for_event(&events, "command").await;
+        assert!(handler.is_background_listening());
+        assert!(!first.lock().iter().any(|e| e.event_type == "command"));
+        assert!(!events.lock().iter().any(|e| e.event_type == "stopped"));
+
+        handler.stop_background_listening().unwrap();
+        wait_for_event(&events, "stopped").await;
+    }
+
+    #[tokio::test]
+    async fn test_command_from_wav_fixture() {
+        // A second of mains hum, a second of speech over it, then the hum again
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("command.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..16000 * 3 {
+            let t = i as f32 / 16000.0;
+            let hum = 0.005 * (2.0 * std::f32::consts::PI * 60.0 * t).sin();
+            let voice = if (1.0..2.0).contains(&t) {
+                0.2 * (2.0 * std::f32::consts::PI * 200.0 * t).sin()
+            } else {
+                0.0
+            };
+            writer.write_sample(((hum + voice) * i16::MAX as f32) as i16).unwrap();
         }
+        writer.finalize().unwrap();
+
+        let recognizer = Arc::new(MockRecognizer::new(&["Kiku", "Start the workflow."]));
+        let source = FileSource::open_wav(&path, Playback::RealTime).unwrap();
+        let (handler, events) =
+            listening_handler(Arc::clone(&recognizer) as Arc<dyn SpeechRecognizer>, source);
+
+        wait_for_event(&events, "command").await;
+        handler.stop_background_listening().unwrap();
+
+        let types: Vec<_> = events.lock().iter().map(|e| e.event_type.clone()).collect();
+        assert_eq!(types[..3], ["listening", "wake_word_detected", "command"]);
+        assert_eq!(
+            events.lock()[2].command.as_ref().map(|c| c.text.as_str()),
+            Some("Start the workflow.")
+        );
+
+        // The recording held the speech and was ended by the silence after it,
+        // long before the ten second limit
+        let calls = recognizer.calls.lock().clone();
+        assert_eq!(calls[0].1, Priority::WakeWord);
+        let (command_len, priority) = calls[1];
+        assert_eq!(priority, Priority::Command);
+        assert!(
+            (16000 * 2..16000 * 8).contains(&command_len),
+            "recorded {} samples",
+            command_len
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quiet_command_after_loud_wake_word() {
+        // Stereo: a long, loud wake word, mains hum, a quiet command, then the hum again
+        let format = AudioFormat {
+            sample_rate: 16000,
+            channels: 2,
+            sample_format: SampleFormat::F32,
+        };
+        let samples: Vec<f32> = (0..16000 * 8)
+            .flat_map(|i| {
+                let t = i as f32 / 16000.0;
+                let tone = |amplitude: f32, hz: f32| {
+                    amplitude * (2.0 * std::f32::consts::PI * hz * t).sin()
+                };
+                let sample = tone(0.005, 60.0)
+                    + match t {
+                        t if t < 2.0 => tone(0.3, 300.0),
+                        t if (3.0..4.0).contains(&t) => tone(0.03, 200.0),
+                        _ => 0.0,
+                    };
+                [sample, sample]
+            })
+            .collect();
+        let source = FileSource::from_samples(samples, format, Playback::RealTime);
+
+        let recognizer = Arc::new(MockRecognizer::new(&["Start the workflow."]));
+        let handler = VoiceCommandHandler::with_backend(
+            Arc::clone(&recognizer) as Arc<dyn SpeechRecognizer>,
+            Arc::new(Mutex::new(source)),
+        );
+        handler.initialize().unwrap();
+        handler.set_streaming(false);
+
+        // The listener has heard nothing but the wake word when it fires
+        handler.source.lock().open().unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let command = handler.record_command_with_vad(&|_| {}).await.unwrap();
+        assert_eq!(command.text, "Start the workflow.");
 
-        // Return None if no command matched
-        None
+        // Had the wake word set the noise floor, the recording would have stopped before the
+        // command was spoken; instead it runs until 1.5 s after it ends
+        let (command_len, priority) = recognizer.calls.lock()[0];
+        assert_eq!(priority, Priority::Command);
+        assert!(command_len >= 16000 * 3, "recorded {} samples", command_len);
     }
 }
@@ -1,68 +1,1139 @@
+use crate::grammar::{CommandGrammar, TokenConstraint, Vocabulary};
+use crate::model_info;
+use crate::worker::{JobQueue, Priority};
 use anyhow::{Context, Result};
 use parking_lot::Mutex;
-use std::path::PathBuf;
-use std::sync::Arc;
-use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+use serde::{Deserialize, Serialize};
+use std::ffi::{c_int, c_void};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use whisper_rs::{
+    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState,
+    WhisperSysContext, WhisperSysState, WhisperTokenData,
+};
 
+/// Decoding parameters passed to Whisper on every transcription
+///
+/// Missing fields deserialize to their defaults so older saved settings keep working.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TranscriptionOptions {
+    /// Spoken language code such as "en" or "de", None to detect it from the audio
+    pub language: Option<String>,
+    /// CPU threads used for decoding
+    pub threads: u32,
+    /// Beam search width, None for greedy sampling
+    pub beam_size: Option<u32>,
+    /// Initial sampling temperature
+    pub temperature: f32,
+    /// Temperature increase when decoding fails its quality checks, 0 disables fallback
+    pub temperature_fallback: f32,
+    /// Text fed to the decoder as if it preceded the audio
+    pub initial_prompt: Option<String>,
+    /// Don't condition on text from previous segments
+    pub no_context: bool,
+    /// Force the whole clip into a single segment
+    pub single_segment: bool,
+    /// Suppress blank output at the start of a segment
+    pub suppress_blank: bool,
+    /// Estimate per-word timings, at some extra decoding cost
+    pub word_timestamps: bool,
+    /// Append wake words, command phrases and custom vocabulary to the initial prompt
+    pub vocabulary_biasing: bool,
+    /// Give up on a transcription after this long, including time spent queued; None waits forever
+    pub timeout_ms: Option<u64>,
+    /// Drop segments that look made up: annotations, stock phrases and runaway repetition
+    pub filter_hallucinations: bool,
+}
+
+impl Default for TranscriptionOptions {
+    fn default() -> Self {
+        Self {
+            language: Some("en".to_string()),
+            threads: 4,
+            beam_size: None,
+            temperature: 0.0,
+            temperature_fallback: 0.2,
+            initial_prompt: None,
+            no_context: true,
+            single_segment: false,
+            suppress_blank: true,
+            word_timestamps: false,
+            vocabulary_biasing: true,
+            timeout_ms: None,
+            filter_hallucinations: true,
+        }
+    }
+}
+
+/// Why a transcription stopped before producing a result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupted {
+    /// Stopped by `WhisperTranscriber::cancel`
+    Cancelled,
+    /// Ran past `TranscriptionOptions::timeout_ms`
+    TimedOut,
+}
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Interrupted::Cancelled => write!(f, "Transcription cancelled"),
+            Interrupted::TimedOut => write!(f, "Transcription timed out"),
+        }
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Text produced by a transcription and how much Whisper trusted it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Transcription {
+    pub text: String,
+    /// Geometric mean probability of the text tokens (exp of the mean log probability), in 0..=1
+    pub confidence: f32,
+    /// Language code the audio was decoded as, either requested or detected
+    pub language: String,
+    /// How sure Whisper was of the language, None when it wasn't detected
+    pub language_probability: Option<f32>,
+    pub segments: Vec<Segment>,
+    /// Segments the hallucination filter left out of `text` and `segments`
+    pub filtered: Vec<FilteredText>,
+}
+
+/// A stretch of text Whisper decoded as one unit, timed from the start of the audio
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Segment {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub text: String,
+    /// Mean probability of the segment's text tokens
+    pub avg_prob: f32,
+    /// Probability the audio held no speech, None when the backend doesn't report it
+    /// (the bundled whisper.cpp masks the no-speech token before it can be read)
+    pub no_speech_prob: Option<f32>,
+    /// Empty unless word timestamps were requested
+    pub words: Vec<Word>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Word {
+    pub text: String,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// Mean probability of the word's tokens
+    pub probability: f32,
+}
+
+/// A decoded text token, before tokens are joined into words
+struct TokenTiming {
+    /// Raw bytes, since multi-byte characters can be split across tokens
+    bytes: Vec<u8>,
+    start_ms: u64,
+    end_ms: u64,
+    probability: f32,
+}
+
+/// Worker threads, each decoding with its own state, so a command isn't stuck behind a long file
+const WORKER_THREADS: usize = 2;
+
+/// A loaded model, the file it came from and the decoding states not currently in use
+struct LoadedModel {
+    ctx: Arc<WhisperContext>,
+    path: PathBuf,
+    /// Text tokens indexed for command mode, read once rather than per decode
+    vocabulary: Arc<Vocabulary>,
+    idle_states: Vec<WhisperState>,
+}
+
+/// A queued transcription and where to send its result
+struct Job {
+    audio: Vec<f32>,
+    options: TranscriptionOptions,
+    grammar: Option<CommandGrammar>,
+    /// Set to stop the job, whether it's still queued or already decoding
+    cancelled: Arc<AtomicBool>,
+    reply: oneshot::Sender<Result<Transcription>>,
+}
+
+/// Cancels a job when the caller stops waiting for it, so abandoned decodes don't hold a worker
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Runs transcriptions on dedicated worker threads that keep their Whisper states between jobs
+///
+/// Jobs wait in a priority queue, commands ahead of wake word checks ahead of files.
 pub struct WhisperTranscriber {
-    ctx: Arc<Mutex<Option<WhisperContext>>>,
-    model_path: PathBuf,
+    model: Arc<Mutex<Option<LoadedModel>>>,
+    /// Model to load before one is loaded; afterwards the loaded model's own path is used
+    model_path: Mutex<PathBuf>,
+    queue: Arc<JobQueue<Job>>,
+    /// Cancel flags of the jobs `cancel` can stop, dropped once they finish
+    cancellable: Mutex<Vec<Weak<AtomicBool>>>,
 }
 
 impl WhisperTranscriber {
     pub fn new(model_path: PathBuf) -> Self {
+        Self::with_workers(model_path, WORKER_THREADS)
+    }
+
+    /// Transcriber decoding on `workers` threads, for models that shouldn't take as much CPU
+    pub fn with_workers(model_path: PathBuf, workers: usize) -> Self {
+        let model = Arc::new(Mutex::new(None));
+        let queue = Arc::new(JobQueue::new());
+
+        for i in 0..workers.max(1) {
+            let model = Arc::clone(&model);
+            let queue = Arc::clone(&queue);
+            std::thread::Builder::new()
+                .name(format!("whisper-worker-{}", i))
+                .spawn(move || run_worker(&model, &queue))
+                .expect("Failed to spawn transcription worker");
+        }
+
         Self {
-            ctx: Arc::new(Mutex::new(None)),
-            model_path,
+            model,
+            model_path: Mutex::new(model_path),
+            queue,
+            cancellable: Mutex::new(Vec::new()),
         }
     }
 
     pub fn load_model(&self) -> Result<()> {
-        let params = WhisperContextParameters::default();
-        let ctx = WhisperContext::new_with_params(&self.model_path.to_string_lossy(), params)
-            .context("Failed to load Whisper model")?;
+        let model_path = self.model_path();
+        self.switch_model(model_path)
+    }
+
+    /// Load another model and swap it in once it's ready
+    ///
+    /// Transcriptions keep using the current model while the new one loads, and jobs already
+    /// decoding finish on it. On failure the current model stays in place.
+    pub fn switch_model(&self, model_path: PathBuf) -> Result<()> {
+        let model = load_context(&model_path)?;
 
-        *self.ctx.lock() = Some(ctx);
+        // The path travels with the model, so it can't be seen out of step with it
+        let mut current = self.model.lock();
+        *self.model_path.lock() = model_path;
+        *current = Some(model);
         Ok(())
     }
 
-    pub fn transcribe(&self, audio_data: &[f32]) -> Result<String> {
-        let ctx = self.ctx.lock();
-        let ctx = ctx.as_ref().context("Whisper model not loaded")?;
-
-        let mut state = ctx.create_state().context("Failed to create state")?;
+    /// Path of the loaded model, or of the one that will be loaded
+    pub fn model_path(&self) -> PathBuf {
+        let current = self.model.lock();
+        match current.as_ref() {
+            Some(model) => model.path.clone(),
+            None => self.model_path.lock().clone(),
+        }
+    }
 
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+    /// Free dictation, unconstrained
+    pub async fn transcribe(
+        &self,
+        audio_data: Vec<f32>,
+        options: TranscriptionOptions,
+        priority: Priority,
+    ) -> Result<Transcription> {
+        self.submit(audio_data, options, None, priority).await
+    }
 
-        // Configure parameters for better command recognition
-        params.set_n_threads(4);
-        params.set_translate(false);
-        params.set_language(Some("en")); // Change this if you need other languages
-        params.set_print_special(false);
-        params.set_print_progress(false);
-        params.set_print_realtime(false);
-        params.set_print_timestamps(false);
+    /// Command mode: decoding is steered towards the utterances `grammar` accepts,
+    /// so near misses like "Stark" come out as the registered phrase
+    pub async fn transcribe_command(
+        &self,
+        audio_data: Vec<f32>,
+        options: TranscriptionOptions,
+        grammar: CommandGrammar,
+    ) -> Result<Transcription> {
+        let grammar = Some(grammar).filter(|grammar| !grammar.is_empty());
+        self.submit(audio_data, options, grammar, Priority::Command).await
+    }
 
-        state
-            .full(params, audio_data)
-            .context("Failed to transcribe audio")?;
+    async fn submit(
+        &self,
+        audio: Vec<f32>,
+        options: TranscriptionOptions,
+        grammar: Option<CommandGrammar>,
+        priority: Priority,
+    ) -> Result<Transcription> {
+        let (reply, result) = oneshot::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let timeout = options.timeout_ms.map(Duration::from_millis);
+        let job = Job {
+            audio,
+            options,
+            grammar,
+            cancelled: Arc::clone(&cancelled),
+            reply,
+        };
 
-        let num_segments = state
-            .full_n_segments()
-            .context("Failed to get number of segments")?;
+        // Wake word checks repeat every hop, so cancelling them would only stall the listener
+        if priority != Priority::WakeWord {
+            let mut cancellable = self.cancellable.lock();
+            cancellable.retain(|flag| flag.strong_count() > 0);
+            cancellable.push(Arc::downgrade(&cancelled));
+        }
+        let _cancel_on_drop = CancelOnDrop(cancelled);
 
-        let mut result = String::new();
-        for i in 0..num_segments {
-            let segment = state
-                .full_get_segment_text(i)
-                .context("Failed to get segment")?;
-            result.push_str(&segment);
+        if self.queue.push(priority, job).is_err() {
+            return Err(anyhow::anyhow!("Transcription workers have stopped"));
         }
 
-        Ok(result.trim().to_string())
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, result)
+                .await
+                .map_err(|_| Interrupted::TimedOut)?,
+            None => result.await,
+        };
+        // Only a shutdown drops queued jobs
+        result.map_err(|_| Interrupted::Cancelled)?
+    }
+
+    /// Stop every queued and running command and file transcription
+    ///
+    /// Their callers get an `Interrupted::Cancelled` error. Returns how many were stopped.
+    pub fn cancel(&self) -> usize {
+        let mut cancellable = self.cancellable.lock();
+        let mut count = 0;
+        for flag in cancellable.drain(..).filter_map(|flag| flag.upgrade()) {
+            if !flag.swap(true, Ordering::SeqCst) {
+                count += 1;
+            }
+        }
+        count
     }
 
     pub fn is_loaded(&self) -> bool {
-        self.ctx.lock().is_some()
+        self.model.lock().is_some()
+    }
+}
+
+impl WhisperTranscriber {
+    /// Stop the workers once they finish the job in hand; queued jobs fail as cancelled
+    pub fn shutdown(&self) {
+        self.queue.close();
+    }
+}
+
+impl Drop for WhisperTranscriber {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn load_context(model_path: &Path) -> Result<LoadedModel> {
+    // whisper.cpp aborts the process on some malformed files, so check the header first
+    model_info::validate(model_path)?;
+
+    let params = WhisperContextParameters::default();
+    let ctx = WhisperContext::new_with_params(&model_path.to_string_lossy(), params)
+        .context("Failed to load Whisper model")?;
+
+    // One state up front so the first transcription doesn't pay for it
+    let state = ctx.create_state().context("Failed to create state")?;
+
+    // Timestamps, end-of-text and other special tokens sort after the text tokens
+    let tokens = (0..ctx.token_eot())
+        .map(|id| Ok(ctx.token_to_cstr(id)?.to_bytes().to_vec()))
+        .collect::<Result<Vec<_>, whisper_rs::WhisperError>>()
+        .context("Failed to read model vocabulary")?;
+    let vocabulary = Vocabulary::new(tokens, ctx.n_vocab() as usize);
+
+    Ok(LoadedModel {
+        ctx: Arc::new(ctx),
+        path: model_path.to_path_buf(),
+        vocabulary: Arc::new(vocabulary),
+        idle_states: vec![state],
+    })
+}
+
+fn run_worker(model: &Mutex<Option<LoadedModel>>, queue: &JobQueue<Job>) {
+    while let Some(job) = queue.pop() {
+        let result = run_job(model, &job);
+        // The caller may have stopped waiting
+        let _ = job.reply.send(result);
+    }
+}
+
+fn run_job(model: &Mutex<Option<LoadedModel>>, job: &Job) -> Result<Transcription> {
+    if job.cancelled.load(Ordering::SeqCst) {
+        return Err(Interrupted::Cancelled.into());
+    }
+
+    // Only hold the lock long enough to borrow a state
+    let (ctx, vocabulary, mut state) = {
+        let mut model = model.lock();
+        let model = model.as_mut().context("Whisper model not loaded")?;
+        let state = match model.idle_states.pop() {
+            Some(state) => state,
+            None => model.ctx.create_state().context("Failed to create state")?,
+        };
+        (Arc::clone(&model.ctx), Arc::clone(&model.vocabulary), state)
+    };
+
+    let result = decode(
+        &ctx,
+        &vocabulary,
+        &mut state,
+        &job.audio,
+        &job.options,
+        job.grammar.as_ref(),
+        &job.cancelled,
+    );
+
+    // Hand the state back for the next job, unless the model was replaced or unloaded meanwhile
+    if let Some(model) = model.lock().as_mut() {
+        if Arc::ptr_eq(&model.ctx, &ctx) {
+            model.idle_states.push(state);
+        }
+    }
+
+    result
+}
+
+/// Decode a whole clip on a state borrowed from the pool
+fn decode(
+    ctx: &WhisperContext,
+    vocabulary: &Arc<Vocabulary>,
+    state: &mut WhisperState,
+    audio_data: &[f32],
+    options: &TranscriptionOptions,
+    grammar: Option<&CommandGrammar>,
+    cancelled: &AtomicBool,
+) -> Result<Transcription> {
+    // Timestamps, end-of-text and other special tokens sort after the text tokens
+    let eot = ctx.token_eot();
+
+    let threads = options.threads.max(1);
+    let (language, language_probability) = match options.language.as_deref() {
+        Some(language) => (language.to_string(), None),
+        None if ctx.is_multilingual() => {
+            let (language, probability) = detect_language(state, audio_data, threads)?;
+            (language.to_string(), Some(probability))
+        }
+        // English-only models can't detect anything else
+        None => ("en".to_string(), None),
+    };
+
+    let strategy = match options.beam_size {
+        Some(beam_size) if beam_size > 1 => SamplingStrategy::BeamSearch {
+            beam_size: beam_size as i32,
+            patience: -1.0,
+        },
+        _ => SamplingStrategy::Greedy { best_of: 1 },
+    };
+    let mut params = FullParams::new(strategy);
+
+    params.set_n_threads(threads as i32);
+    params.set_translate(false);
+    params.set_language(Some(&language));
+    params.set_temperature(options.temperature);
+    params.set_temperature_inc(options.temperature_fallback);
+    if let Some(prompt) = options.initial_prompt.as_deref().filter(|p| !p.is_empty()) {
+        params.set_initial_prompt(prompt);
+    }
+    params.set_no_context(options.no_context);
+    params.set_single_segment(options.single_segment);
+    params.set_suppress_blank(options.suppress_blank);
+    params.set_token_timestamps(options.word_timestamps);
+
+    // whisper-rs 0.12 hands whisper.cpp a flat element array where it expects one
+    // pointer per rule, so grammars go through the logits filter instead
+    let constraint =
+        grammar.map(|grammar| TokenConstraint::new(grammar.clone(), Arc::clone(vocabulary)));
+    if let Some(constraint) = &constraint {
+        // SAFETY: `constraint` outlives `state.full` below, the only place the callback runs,
+        // and is only read through the pointer
+        unsafe {
+            params.set_filter_logits_callback(Some(apply_token_constraint));
+            params.set_filter_logits_callback_user_data(
+                constraint as *const TokenConstraint as *mut c_void,
+            );
+        }
+    }
+    // whisper-rs 0.12's set_abort_callback_safe casts its boxed closure to the wrong type,
+    // so the flag goes through the raw callback
+    // SAFETY: `cancelled` outlives `state.full` below and is only read through the pointer
+    unsafe {
+        params.set_abort_callback(Some(check_cancelled));
+        params.set_abort_callback_user_data(cancelled as *const AtomicBool as *mut c_void);
+    }
+    params.set_print_special(false);
+    params.set_print_progress(false);
+    params.set_print_realtime(false);
+    params.set_print_timestamps(false);
+
+    let full = state.full(params, audio_data);
+    // An aborted decode may fail or return whatever it had so far, neither of which is wanted
+    if cancelled.load(Ordering::SeqCst) {
+        return Err(Interrupted::Cancelled.into());
+    }
+    full.context("Failed to transcribe audio")?;
+
+    let num_segments = state
+        .full_n_segments()
+        .context("Failed to get number of segments")?;
+
+    let mut result = String::new();
+    let mut segments = Vec::new();
+    for i in 0..num_segments {
+        let text = state
+            .full_get_segment_text(i)
+            .context("Failed to get segment")?;
+        result.push_str(&text);
+
+        let num_tokens = state
+            .full_n_tokens(i)
+            .context("Failed to get number of tokens")?;
+        let mut tokens = Vec::new();
+        let mut logprobs = Vec::new();
+        for j in 0..num_tokens {
+            let token = state
+                .full_get_token_data(i, j)
+                .context("Failed to get token data")?;
+            if token.id >= eot {
+                continue;
+            }
+
+            logprobs.push(token.plog);
+            tokens.push(TokenTiming {
+                bytes: ctx
+                    .token_to_cstr(token.id)
+                    .context("Failed to get token text")?
+                    .to_bytes()
+                    .to_vec(),
+                start_ms: centis_to_ms(token.t0),
+                end_ms: centis_to_ms(token.t1),
+                probability: token.p,
+            });
+        }
+
+        let avg_prob = if tokens.is_empty() {
+            0.0
+        } else {
+            tokens.iter().map(|t| t.probability).sum::<f32>() / tokens.len() as f32
+        };
+
+        let start = state
+            .full_get_segment_t0(i)
+            .context("Failed to get segment start")?;
+        let end = state
+            .full_get_segment_t1(i)
+            .context("Failed to get segment end")?;
+
+        let segment = Segment {
+            start_ms: centis_to_ms(start),
+            end_ms: centis_to_ms(end),
+            text: text.trim().to_string(),
+            avg_prob,
+            // whisper.cpp masks the no-speech token and has no API to read it, so local
+            // segments rely on the text filters alone
+            no_speech_prob: None,
+            words: if options.word_timestamps {
+                group_words(&tokens)
+            } else {
+                Vec::new()
+            },
+        };
+        segments.push((segment, logprobs));
+    }
+
+    Ok(assemble(
+        &result,
+        segments,
+        language,
+        language_probability,
+        options.filter_hallucinations,
+    ))
+}
+
+/// Build a transcription from decoded segments, each with its text token log probabilities
+///
+/// With `filter` set, hallucinated segments are dropped and the text is rebuilt from the rest;
+/// otherwise `text` is kept as decoded.
+pub fn assemble(
+    text: &str,
+    segments: Vec<(Segment, Vec<f32>)>,
+    language: String,
+    language_probability: Option<f32>,
+    filter: bool,
+) -> Transcription {
+    let (text, segments, filtered) = if filter {
+        let (segments, filtered) = filter_segments(segments);
+        let text = segments
+            .iter()
+            .map(|(segment, _)| segment.text.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        (text, segments, filtered)
+    } else {
+        (text.trim().to_string(), segments, Vec::new())
+    };
+    let (segments, logprobs): (Vec<_>, Vec<_>) = segments.into_iter().unzip();
+
+    Transcription {
+        text,
+        confidence: utterance_confidence(&logprobs.concat()),
+        language,
+        language_probability,
+        segments,
+        filtered,
+    }
+}
+
+/// No-speech probability above which a segment is dropped...
+///
+/// Only the HTTP backend reports one; local transcriptions skip this check.
+const NO_SPEECH_THRESHOLD: f32 = 0.6;
+/// ...unless its tokens were decoded with more confidence than this mean log probability
+const LOGPROB_THRESHOLD: f32 = -1.0;
+/// Text compressing better than this is the same phrase over and over
+const COMPRESSION_RATIO_THRESHOLD: f32 = 2.4;
+
+/// Phrases Whisper produces from silence and noise, picked up from video subtitles,
+/// lowercase without punctuation
+const KNOWN_HALLUCINATIONS: &[&str] = &[
+    "thank you",
+    "thank you very much",
+    "thank you so much",
+    "thanks for watching",
+    "thank you for watching",
+    "thank you so much for watching",
+    "thanks for watching and see you next time",
+    "please subscribe",
+    "subscribe to my channel",
+    "like and subscribe",
+    "see you next time",
+    "subtitles by the amara org community",
+    "transcription by castingwords",
+    "subtitles by",
+];
+
+/// Stock phrases that are also real one-word answers, only dropped when the segment
+/// looks like silence for some other reason
+const SHORT_REPLIES: &[&str] = &["you", "thanks", "bye", "bye bye"];
+
+/// Why text was left out of a transcription
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FilterReason {
+    /// The backend judged the audio to be silence or noise, only reported by the HTTP backend
+    NoSpeech { probability: f32 },
+    /// The text repeats itself, judged by how well it compresses
+    Repetition { compression_ratio: f32 },
+    /// A stock phrase Whisper is known to produce from silence
+    KnownHallucination,
+    /// Nothing but annotations such as "[BLANK_AUDIO]" or "(music)"
+    NonSpeech,
+}
+
+/// A segment left out of a transcription and why
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilteredText {
+    pub text: String,
+    pub reason: FilterReason,
+}
+
+/// Check one segment, returning its text with annotations stripped or why it should be dropped
+pub fn filter_segment(
+    text: &str,
+    no_speech_prob: Option<f32>,
+    avg_logprob: f32,
+) -> Result<String, FilterReason> {
+    if let Some(probability) = no_speech_prob {
+        if probability > NO_SPEECH_THRESHOLD && avg_logprob < LOGPROB_THRESHOLD {
+            return Err(FilterReason::NoSpeech { probability });
+        }
+    }
+
+    let text = strip_annotations(text);
+    if !text.chars().any(char::is_alphanumeric) {
+        return Err(FilterReason::NonSpeech);
+    }
+    if is_known_hallucination(&text) {
+        return Err(FilterReason::KnownHallucination);
+    }
+
+    let compression_ratio = compression_ratio(&text);
+    if compression_ratio > COMPRESSION_RATIO_THRESHOLD {
+        return Err(FilterReason::Repetition { compression_ratio });
+    }
+
+    // Decoded with confidence, but the backend still thinks there was no speech
+    let likely_silence =
+        no_speech_prob.is_some_and(|probability| probability > NO_SPEECH_THRESHOLD);
+    if likely_silence && matches_phrase(&text, SHORT_REPLIES) {
+        return Err(FilterReason::KnownHallucination);
+    }
+
+    Ok(text)
+}
+
+/// Filter decoded segments, each with its text token log probabilities
+///
+/// Segments that pass individually are also dropped together when they only repeat each other.
+fn filter_segments(
+    segments: Vec<(Segment, Vec<f32>)>,
+) -> (Vec<(Segment, Vec<f32>)>, Vec<FilteredText>) {
+    let mut kept = Vec::new();
+    let mut filtered = Vec::new();
+    for (mut segment, logprobs) in segments {
+        let avg_logprob = if logprobs.is_empty() {
+            f32::NEG_INFINITY
+        } else {
+            logprobs.iter().sum::<f32>() / logprobs.len() as f32
+        };
+
+        match filter_segment(&segment.text, segment.no_speech_prob, avg_logprob) {
+            Ok(text) => {
+                segment.text = text;
+                kept.push((segment, logprobs));
+            }
+            Err(reason) => filtered.push(FilteredText {
+                text: segment.text,
+                reason,
+            }),
+        }
+    }
+
+    let text = kept
+        .iter()
+        .map(|(segment, _)| segment.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let compression_ratio = compression_ratio(&text);
+    if compression_ratio > COMPRESSION_RATIO_THRESHOLD
+        || (kept.len() > 1 && is_known_hallucination(&text))
+    {
+        let reason = if compression_ratio > COMPRESSION_RATIO_THRESHOLD {
+            FilterReason::Repetition { compression_ratio }
+        } else {
+            FilterReason::KnownHallucination
+        };
+        filtered.extend(kept.drain(..).map(|(segment, _)| FilteredText {
+            text: segment.text,
+            reason: reason.clone(),
+        }));
+    }
+
+    (kept, filtered)
+}
+
+/// Remove bracketed annotations like "[BLANK_AUDIO]", "(music)" and "*laughs*", and music notes
+fn strip_annotations(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut closing = Vec::new();
+    for c in text.chars() {
+        match c {
+            '[' => closing.push(']'),
+            '(' => closing.push(')'),
+            '*' if closing.last() == Some(&'*') => {
+                closing.pop();
+            }
+            '*' => closing.push('*'),
+            c if closing.last() == Some(&c) => {
+                closing.pop();
+            }
+            '♪' | '♫' | '♬' | '♩' => {}
+            c if closing.is_empty() => stripped.push(c),
+            _ => {}
+        }
+    }
+
+    stripped.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether `text` is a known hallucination, alone or repeated
+fn is_known_hallucination(text: &str) -> bool {
+    matches_phrase(text, KNOWN_HALLUCINATIONS)
+}
+
+/// Whether `text` is one of `phrases`, alone or repeated, ignoring case and punctuation
+fn matches_phrase(text: &str, phrases: &[&str]) -> bool {
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.is_empty() {
+        return false;
+    }
+
+    phrases.iter().any(|phrase| {
+        let phrase: Vec<&str> = phrase.split(' ').collect();
+        words.len() % phrase.len() == 0
+            && words.chunks(phrase.len()).all(|chunk| chunk == phrase)
+    })
+}
+
+/// Size of `text` over its zlib-compressed size, as OpenAI's Whisper measures repetition
+fn compression_ratio(text: &str) -> f32 {
+    if text.is_empty() {
+        return 0.0;
+    }
+
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    // Writing to a Vec can't fail
+    let compressed = encoder
+        .write_all(text.as_bytes())
+        .and_then(|_| encoder.finish())
+        .map(|compressed| compressed.len())
+        .unwrap_or(text.len());
+
+    text.len() as f32 / compressed.max(1) as f32
+}
+
+/// Longest vocabulary list put in a prompt, well inside the 224 prompt tokens Whisper keeps
+const MAX_VOCABULARY_PROMPT_CHARS: usize = 400;
+
+/// Initial prompt nudging decoding towards `vocabulary`, after any prompt the user set
+///
+/// Terms are listed in the order given, so the most important should come first; case-insensitive
+/// duplicates are dropped and the list is cut short rather than exceed the prompt budget.
+pub fn vocabulary_prompt(user_prompt: Option<&str>, vocabulary: &[String]) -> Option<String> {
+    let mut seen = std::collections::HashSet::new();
+    let mut terms = Vec::new();
+    let mut length = 0;
+    for term in vocabulary {
+        let term = term.trim();
+        if term.is_empty() || !seen.insert(term.to_lowercase()) {
+            continue;
+        }
+        if length + term.len() + 2 > MAX_VOCABULARY_PROMPT_CHARS {
+            break;
+        }
+        length += term.len() + 2;
+        terms.push(term);
+    }
+
+    // Whisper keeps the end of an over-long prompt, so the vocabulary goes last
+    let user_prompt = user_prompt.map(str::trim).filter(|p| !p.is_empty());
+    match (user_prompt, terms.is_empty()) {
+        (None, true) => None,
+        (Some(prompt), true) => Some(prompt.to_string()),
+        (None, false) => Some(format!("{}.", terms.join(", "))),
+        (Some(prompt), false) => Some(format!("{} {}.", prompt, terms.join(", "))),
+    }
+}
+
+/// Logits filter enforcing a `TokenConstraint` passed as user data
+unsafe extern "C" fn apply_token_constraint(
+    _ctx: *mut WhisperSysContext,
+    _state: *mut WhisperSysState,
+    tokens: *const WhisperTokenData,
+    n_tokens: c_int,
+    logits: *mut f32,
+    user_data: *mut c_void,
+) {
+    if user_data.is_null() || logits.is_null() {
+        return;
+    }
+
+    let constraint = &*(user_data as *const TokenConstraint);
+    let generated: Vec<i32> = if tokens.is_null() || n_tokens <= 0 {
+        Vec::new()
+    } else {
+        std::slice::from_raw_parts(tokens, n_tokens as usize)
+            .iter()
+            .map(|token| token.id)
+            .collect()
+    };
+    let logits = std::slice::from_raw_parts_mut(logits, constraint.n_vocab());
+
+    constraint.apply(&generated, logits);
+}
+
+/// Abort callback stopping whisper.cpp between compute steps once a job's cancel flag is set
+unsafe extern "C" fn check_cancelled(user_data: *mut c_void) -> bool {
+    if user_data.is_null() {
+        return false;
+    }
+
+    (*(user_data as *const AtomicBool)).load(Ordering::SeqCst)
+}
+
+/// Most likely spoken language and its probability, from the first 30 seconds of audio
+fn detect_language(
+    state: &mut WhisperState,
+    audio_data: &[f32],
+    threads: u32,
+) -> Result<(&'static str, f32)> {
+    state
+        .pcm_to_mel(audio_data, threads as usize)
+        .context("Failed to compute spectrogram for language detection")?;
+    let (id, probabilities) = state
+        .lang_detect(0, threads as usize)
+        .context("Failed to detect language")?;
+
+    let language = whisper_rs::get_lang_str(id).context("Whisper detected an unknown language")?;
+    let probability = probabilities.get(id as usize).copied().unwrap_or(0.0);
+    Ok((language, probability))
+}
+
+/// Confidence of an utterance from its token log probabilities
+///
+/// The bundled whisper.cpp suppresses the no-speech token before sampling and
+/// doesn't report its probability, so an utterance with no text tokens is
+/// treated as no speech and gets zero confidence.
+fn utterance_confidence(logprobs: &[f32]) -> f32 {
+    if logprobs.is_empty() {
+        return 0.0;
+    }
+
+    let avg_logprob = logprobs.iter().sum::<f32>() / logprobs.len() as f32;
+    avg_logprob.exp().clamp(0.0, 1.0)
+}
+
+/// Whisper reports times in hundredths of a second
+fn centis_to_ms(centis: i64) -> u64 {
+    centis.max(0) as u64 * 10
+}
+
+/// Join sub-word tokens into words, starting a new word at each leading space
+fn group_words(tokens: &[TokenTiming]) -> Vec<Word> {
+    let mut groups: Vec<Vec<&TokenTiming>> = Vec::new();
+    for token in tokens {
+        match groups.last_mut() {
+            Some(group) if !token.bytes.starts_with(b" ") => group.push(token),
+            _ => groups.push(vec![token]),
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter_map(|group| {
+            let bytes: Vec<u8> = group.iter().flat_map(|t| t.bytes.iter().copied()).collect();
+            let text = String::from_utf8_lossy(&bytes).trim().to_string();
+            if text.is_empty() {
+                return None;
+            }
+
+            Some(Word {
+                text,
+                start_ms: group[0].start_ms,
+                end_ms: group[group.len() - 1].end_ms,
+                probability: group.iter().map(|t| t.probability).sum::<f32>() / group.len() as f32,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_options_use_defaults() {
+        let options: TranscriptionOptions =
+            serde_json::from_str(r#"{"language": null, "threads": 16, "beam_size": 5}"#).unwrap();
+
+        assert_eq!(options.language, None);
+        assert_eq!(options.threads, 16);
+        assert_eq!(options.beam_size, Some(5));
+        assert_eq!(options.temperature_fallback, 0.2);
+        assert!(options.suppress_blank);
+        assert_eq!(options.initial_prompt, None);
+    }
+
+    #[test]
+    fn test_interrupted_survives_context() {
+        let error =
+            anyhow::Error::from(Interrupted::Cancelled).context("Failed to transcribe audio");
+
+        assert_eq!(
+            error.downcast_ref::<Interrupted>(),
+            Some(&Interrupted::Cancelled)
+        );
+        assert_eq!(Interrupted::TimedOut.to_string(), "Transcription timed out");
+    }
+
+    #[test]
+    fn test_utterance_confidence() {
+        assert_eq!(utterance_confidence(&[0.0, 0.0]), 1.0);
+
+        // Geometric mean of 0.9 and 0.1 is 0.3
+        let confidence = utterance_confidence(&[0.9f32.ln(), 0.1f32.ln()]);
+        assert!((confidence - 0.3).abs() < 1e-5);
+
+        // No text tokens means nothing was said
+        assert_eq!(utterance_confidence(&[]), 0.0);
+    }
+
+    fn segment(text: &str, no_speech_prob: Option<f32>) -> (Segment, Vec<f32>) {
+        let segment = Segment {
+            start_ms: 0,
+            end_ms: 1000,
+            text: text.to_string(),
+            avg_prob: 0.5,
+            no_speech_prob,
+            words: Vec::new(),
+        };
+        (segment, vec![-1.5, -2.0])
+    }
+
+    #[test]
+    fn test_filter_segment() {
+        assert_eq!(
+            filter_segment("[BLANK_AUDIO]", None, -0.2),
+            Err(FilterReason::NonSpeech)
+        );
+        assert_eq!(
+            filter_segment(" (music) ♪ ", None, -0.2),
+            Err(FilterReason::NonSpeech)
+        );
+        assert_eq!(
+            filter_segment("Thank you.", None, -0.2),
+            Err(FilterReason::KnownHallucination)
+        );
+        assert_eq!(
+            filter_segment("Thank you. Thank you. Thank you.", None, -0.2),
+            Err(FilterReason::KnownHallucination)
+        );
+        assert_eq!(
+            filter_segment("Start the build", Some(0.9), -1.2),
+            Err(FilterReason::NoSpeech { probability: 0.9 })
+        );
+        assert!(matches!(
+            filter_segment(&"open the door ".repeat(12), None, -0.2),
+            Err(FilterReason::Repetition { .. })
+        ));
+
+        // Confident decodes survive a high no-speech probability, annotations are stripped
+        assert_eq!(
+            filter_segment("[laughs] Start the build", Some(0.9), -0.3).as_deref(),
+            Ok("Start the build")
+        );
+        assert_eq!(
+            filter_segment("Thank you, start the build", None, -0.2).as_deref(),
+            Ok("Thank you, start the build")
+        );
+
+        // One-word answers only go when the backend also hears silence
+        assert_eq!(filter_segment("You.", None, -0.2).as_deref(), Ok("You."));
+        assert_eq!(filter_segment("Thanks!", Some(0.1), -0.2).as_deref(), Ok("Thanks!"));
+        assert_eq!(
+            filter_segment("Bye.", Some(0.7), -0.5),
+            Err(FilterReason::KnownHallucination)
+        );
+    }
+
+    #[test]
+    fn test_filter_segments() {
+        let (kept, filtered) = filter_segments(vec![
+            segment("[BLANK_AUDIO]", None),
+            segment("Stop the workflow.", None),
+            segment("Thanks for watching!", None),
+        ]);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].0.text, "Stop the workflow.");
+        assert_eq!(filtered.len(), 2);
+        assert_eq!(filtered[1].text, "Thanks for watching!");
+
+        // Segments that pass alone are dropped when together they make up a stock phrase
+        let (kept, filtered) = filter_segments(vec![
+            segment("Thanks for", None),
+            segment("watching.", None),
+        ]);
+        assert!(kept.is_empty());
+        assert_eq!(filtered.len(), 2);
+    }
+
+    #[test]
+    fn test_local_segments_are_filtered() {
+        // The local backend never reports a no-speech probability, the text filters still apply
+        let transcription = assemble(
+            "",
+            vec![
+                segment("[BLANK_AUDIO]", None),
+                segment("Thank you.", None),
+                segment(&"open the door ".repeat(12), None),
+                segment("Open the door.", None),
+            ],
+            "en".to_string(),
+            None,
+            true,
+        );
+
+        assert_eq!(transcription.text, "Open the door.");
+        let reasons: Vec<_> = transcription
+            .filtered
+            .iter()
+            .map(|filtered| &filtered.reason)
+            .collect();
+        assert!(matches!(
+            reasons[..],
+            [
+                FilterReason::NonSpeech,
+                FilterReason::KnownHallucination,
+                FilterReason::Repetition { .. }
+            ]
+        ));
+    }
+
+    fn token(text: &[u8], start_ms: u64, end_ms: u64, probability: f32) -> TokenTiming {
+        TokenTiming {
+            bytes: text.to_vec(),
+            start_ms,
+            end_ms,
+            probability,
+        }
+    }
+
+    #[test]
+    fn test_group_words() {
+        // "é" is split across two tokens, "." attaches to the word before it
+        let tokens = vec![
+            token(b" Caf", 0, 200, 0.9),
+            token(&[0xC3], 200, 250, 0.7),
+            token(&[0xA9], 250, 300, 0.8),
+            token(b" open", 400, 600, 0.6),
+            token(b".", 600, 650, 1.0),
+        ];
+
+        let words = group_words(&tokens);
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[0].text, "Café");
+        assert_eq!((words[0].start_ms, words[0].end_ms), (0, 300));
+        assert!((words[0].probability - 0.8).abs() < 1e-6);
+        assert_eq!(words[1].text, "open.");
+        assert_eq!((words[1].start_ms, words[1].end_ms), (400, 650));
+    }
+
+    #[test]
+    fn test_vocabulary_prompt() {
+        let vocabulary: Vec<String> = ["Kiku", "computer", " ", "Hyperion", "kiku", "start"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+
+        assert_eq!(
+            vocabulary_prompt(None, &vocabulary).as_deref(),
+            Some("Kiku, computer, Hyperion, start.")
+        );
+        assert_eq!(
+            vocabulary_prompt(Some("Project meeting."), &vocabulary[..1]).as_deref(),
+            Some("Project meeting. Kiku.")
+        );
+        assert_eq!(vocabulary_prompt(Some("  "), &[]), None);
+
+        // Long lists are cut short instead of pushing the user prompt out
+        let long: Vec<String> = (0..200).map(|i| format!("term{}", i)).collect();
+        let prompt = vocabulary_prompt(None, &long).unwrap();
+        assert!(prompt.len() <= MAX_VOCABULARY_PROMPT_CHARS);
+        assert!(prompt.starts_with("term0, term1,"));
     }
 }
@@ -0,0 +1,151 @@
+//! Transcription queue module
+//! Orders pending transcription jobs by priority for the worker threads that own the Whisper states
+
+use parking_lot::{Condvar, Mutex};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Who a job is for; higher priorities are decoded first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Whole files, where a few hundred milliseconds more don't matter
+    File,
+    /// The background listener's rolling window
+    WakeWord,
+    /// A command the user is waiting on, including its partial transcripts
+    Command,
+}
+
+struct Queued<T> {
+    priority: Priority,
+    /// Submission order, so jobs of equal priority run first in, first out
+    seq: u64,
+    job: T,
+}
+
+impl<T> PartialEq for Queued<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Queued<T> {}
+
+impl<T> PartialOrd for Queued<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Queued<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap pops the greatest, so earlier submissions must compare greater
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+struct QueueState<T> {
+    jobs: BinaryHeap<Queued<T>>,
+    next_seq: u64,
+    closed: bool,
+}
+
+/// Blocking priority queue shared between submitters and worker threads
+pub struct JobQueue<T> {
+    state: Mutex<QueueState<T>>,
+    ready: Condvar,
+}
+
+impl<T> JobQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                jobs: BinaryHeap::new(),
+                next_seq: 0,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Queue a job, handing it back if the queue has been closed
+    pub fn push(&self, priority: Priority, job: T) -> Result<(), T> {
+        let mut state = self.state.lock();
+        if state.closed {
+            return Err(job);
+        }
+
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.jobs.push(Queued { priority, seq, job });
+        drop(state);
+
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    /// Wait for the most urgent job, or None once the queue is closed
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some(queued) = state.jobs.pop() {
+                return Some(queued.job);
+            }
+            self.ready.wait(&mut state);
+        }
+    }
+
+    /// Stop the workers and drop every job still waiting
+    pub fn close(&self) {
+        let mut state = self.state.lock();
+        state.closed = true;
+        state.jobs.clear();
+        drop(state);
+
+        self.ready.notify_all();
+    }
+}
+
+impl<T> Default for JobQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_priority_then_submission_order() {
+        let queue = JobQueue::new();
+        queue.push(Priority::File, "file").unwrap();
+        queue.push(Priority::WakeWord, "wake 1").unwrap();
+        queue.push(Priority::Command, "command").unwrap();
+        queue.push(Priority::WakeWord, "wake 2").unwrap();
+
+        let order: Vec<_> = (0..4).map(|_| queue.pop().unwrap()).collect();
+        assert_eq!(order, vec!["command", "wake 1", "wake 2", "file"]);
+    }
+
+    #[test]
+    fn test_close_wakes_waiting_workers() {
+        let queue = Arc::new(JobQueue::<u32>::new());
+        let worker = {
+            let queue = Arc::clone(&queue);
+            std::thread::spawn(move || queue.pop())
+        };
+
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        queue.close();
+
+        assert_eq!(worker.join().unwrap(), None);
+        assert_eq!(queue.push(Priority::Command, 1), Err(1));
+    }
+}
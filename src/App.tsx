@@ -1,12 +1,94 @@
 import { useState, useEffect, useRef } from 'react';
 import { invoke } from '@tauri-apps/api/core';
-import type { VoiceCommand, Message, CommandType } from './types';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
+import type {
+  VoiceCommand,
+  InstalledModel,
+  Message,
+  CommandType,
+  ListeningEvent,
+  ModelSwitchEvent,
+  PartialTranscript,
+  TranscriptionOptions,
+  CommandMapping,
+  SpeechBackend,
+  VadMode,
+} from './types';
 import { COMMAND_MESSAGES } from './types';
 import Settings from './components/Settings';
 import { Store } from '@tauri-apps/plugin-store';
 
 type View = 'main' | 'settings';
 
+// Push saved decoding options, vocabulary, command mappings and the wake word model to the
+// backend, which starts from defaults on every initialization
+async function applySavedTranscriptionOptions(): Promise<void> {
+  try {
+    const store = await Store.load('settings.json');
+    const savedOptions = await store.get<TranscriptionOptions>('transcriptionOptions');
+    if (savedOptions) {
+      await invoke('set_transcription_options', { options: savedOptions });
+    }
+
+    const savedCommandMode = await store.get<boolean>('commandMode');
+    if (savedCommandMode !== null && savedCommandMode !== undefined) {
+      await invoke('set_command_mode', { enabled: savedCommandMode });
+    }
+
+    const savedStreaming = await store.get<boolean>('streaming');
+    if (savedStreaming !== null && savedStreaming !== undefined) {
+      await invoke('set_streaming', { enabled: savedStreaming });
+    }
+
+    const savedVadMode = await store.get<VadMode>('vadMode');
+    if (savedVadMode) {
+      await invoke('set_vad_mode', { mode: savedVadMode });
+    }
+
+    const savedVocabulary = await store.get<string[]>('customVocabulary');
+    if (savedVocabulary) {
+      await invoke('set_custom_vocabulary', { vocabulary: savedVocabulary });
+    }
+
+    // Per-language overrides of the built-in command phrases, keyed by language code
+    const savedMappings = await store.get<Record<string, CommandMapping[]>>('commandMappings');
+    for (const [language, mappings] of Object.entries(savedMappings ?? {})) {
+      await invoke('set_command_mappings', { language, mappings });
+    }
+
+    // Last, since loading a model is slow and fails if the file has been removed
+    const savedWakeModel = await store.get<string>('wakeModel');
+    if (savedWakeModel) {
+      const modelPath = await invoke<string>('get_model_path', { modelName: savedWakeModel });
+      await invoke('set_wake_model', { modelPath });
+    }
+  } catch (error) {
+    console.log('Error setting transcription options:', error);
+  }
+}
+
+// Start the voice system on the saved backend: the local model at modelPath unless a
+// transcription server is configured
+async function initializeVoice(modelPath: string): Promise<string> {
+  const store = await Store.load('settings.json');
+  const backend = await store.get<SpeechBackend>('speechBackend');
+  if (backend?.kind === 'remote') {
+    return invoke<string>('initialize_remote_voice', {
+      baseUrl: backend.base_url,
+      apiKey: backend.api_key || null,
+      model: backend.model || null,
+    });
+  }
+  return invoke<string>('initialize_voice', { modelPath });
+}
+
+// Whether there's a transcription server to initialize against without a model path
+async function usesRemoteBackend(): Promise<boolean> {
+  const store = await Store.load('settings.json');
+  const backend = await store.get<SpeechBackend>('speechBackend');
+  return backend?.kind === 'remote';
+}
+
 function App() {
   const [currentView, setCurrentView] = useState<View>('main');
   const [modelPath, setModelPath] = useState<string>('C:/models/ggml-base.en.bin');
@@ -20,6 +102,9 @@ function App() {
   const [commandHistory, setCommandHistory] = useState<VoiceCommand[]>([]);
   const [logFilePath, setLogFilePath] = useState<string>('');
   const initializingRef = useRef<boolean>(false);
+  const [partialTranscript, setPartialTranscript] = useState<PartialTranscript | null>(null);
+  const unlistenRef = useRef<UnlistenFn | null>(null);
+  const unlistenPartialRef = useRef<UnlistenFn | null>(null);
 
   // Load saved settings on mount and auto-initialize
   useEffect(() => {
@@ -34,10 +119,12 @@ function App() {
         // If no saved path, check for models in AppData directory
         if (!savedPath) {
           try {
-            const availableModels = await invoke<string[]>('list_available_models');
+            const availableModels = await invoke<InstalledModel[]>('list_available_models');
             if (availableModels.length > 0) {
               // Use the first available model
-              savedPath = await invoke<string>('get_model_path', { modelName: availableModels[0] });
+              savedPath = await invoke<string>('get_model_path', {
+                modelName: availableModels[0].file_name,
+              });
               // Save this path for future use
               await store.set('modelPath', savedPath);
               await store.save();
@@ -56,14 +143,15 @@ function App() {
         setIsInitialized(initialized);
 
         // Auto-initialize and start background listening if not already initialized
-        if (!initialized && savedPath && !initializingRef.current) {
+        const remote = await usesRemoteBackend();
+        if (!initialized && (savedPath || remote) && !initializingRef.current) {
           initializingRef.current = true;
           try {
             setIsProcessing(true);
             setTranscriptionText('Initializing voice system...');
 
             // Initialize the voice system
-            await invoke<string>('initialize_voice', { modelPath: savedPath });
+            await initializeVoice(savedPath ?? '');
             setIsInitialized(true);
 
             // Set audio device after initialization
@@ -77,14 +165,16 @@ function App() {
               }
             }
 
+            await applySavedTranscriptionOptions();
+
+            // Subscribe before starting so the first events aren't missed
+            await startWakeWordDetection();
+
             // Start background listening
             await invoke<string>('start_background_listening');
             setIsListening(true);
             setTranscriptionText('Listening for wake word ("kiku" or "computer")...');
 
-            // Start polling for wake word detection (deferred to next tick)
-            setTimeout(() => void startWakeWordDetection(), 0);
-
             setMessage({
               type: 'success',
               text: 'Voice system initialized and listening started automatically'
@@ -118,7 +208,21 @@ function App() {
     void loadSettings();
   }, []);
 
-  // Save model path when it changes
+  // Report model switches, which load in the background while listening continues
+  useEffect(() => {
+    const unlisten = listen<ModelSwitchEvent>('model_switch', event => {
+      const { status, message } = event.payload;
+      setMessage({
+        type: status === 'failed' ? 'error' : status === 'loaded' ? 'success' : 'info',
+        text: message,
+      });
+    });
+    return () => {
+      void unlisten.then(fn => fn());
+    };
+  }, []);
+
+  // Save model path when it changes, and swap it in if the voice system is already running
   const handleModelPathChange = async (newPath: string): Promise<void> => {
     setModelPath(newPath);
     try {
@@ -128,10 +232,19 @@ function App() {
     } catch (error) {
       console.error('Failed to save model path', error);
     }
+
+    if (isInitialized) {
+      try {
+        await invoke<string>('switch_model', { modelPath: newPath });
+      } catch (error) {
+        // Load failures also arrive as a model_switch event; the previous model stays loaded
+        console.error('Failed to switch model', error);
+      }
+    }
   };
 
   const handleInitialize = async (): Promise<void> => {
-    if (!modelPath) {
+    if (!modelPath && !(await usesRemoteBackend())) {
       setMessage({ type: 'error', text: 'Please enter a model path' });
       return;
     }
@@ -139,7 +252,8 @@ function App() {
     try {
       setIsProcessing(true);
       setMessage(null);
-      const result = await invoke<string>('initialize_voice', { modelPath });
+      const result = await initializeVoice(modelPath);
+      await applySavedTranscriptionOptions();
       setIsInitialized(true);
       setMessage({ type: 'success', text: result });
     } catch (error) {
@@ -160,12 +274,10 @@ function App() {
 
     try {
       setMessage(null);
+      await startWakeWordDetection();
       await invoke<string>('start_background_listening');
       setIsListening(true);
       setTranscriptionText('Listening for wake word ("kiku" or "computer")...');
-
-      // Start polling for wake word detection
-      startWakeWordDetection();
     } catch (error) {
       setMessage({
         type: 'error',
@@ -188,22 +300,52 @@ function App() {
     }
   };
 
+  // Stop the command being transcribed; the listener reports it as a cancelled event
+  const handleCancelTranscription = async (): Promise<void> => {
+    try {
+      await invoke<boolean>('cancel_transcription');
+    } catch (error) {
+      console.error('Failed to cancel transcription', error);
+    }
+  };
+
   const startWakeWordDetection = async (): Promise<void> => {
-    while (true) {
-      try {
-        // Check if still listening
-        const listening = await invoke<boolean>('is_background_listening');
-        if (!listening) {
-          setIsListening(false);
-          break;
-        }
+    // The backend owns the listening loop; we only react to its events
+    unlistenRef.current?.();
+    unlistenRef.current = await listen<ListeningEvent>('listening_event', event => {
+      void handleListeningEvent(event.payload);
+    });
+
+    unlistenPartialRef.current?.();
+    unlistenPartialRef.current = await listen<PartialTranscript>('partial_transcript', event => {
+      // The final event is followed by the command itself, which replaces the partial text
+      setPartialTranscript(event.payload.is_final ? null : event.payload);
+    });
+  };
 
-        // Record with VAD (will auto-stop on silence)
+  const handleListeningEvent = async (event: ListeningEvent): Promise<void> => {
+    switch (event.event_type) {
+      case 'listening':
         setTranscriptionText('Say "kiku" or "computer" followed by your command...');
-        const voiceCommand = await invoke<VoiceCommand>('record_command_with_vad');
+        break;
+      case 'wake_word_detected':
+        setMessage({ type: 'info', text: 'Wake word detected!' });
+        setTranscriptionText('Listening for your command...');
+        break;
+      case 'command': {
+        const voiceCommand = event.command;
+        if (!voiceCommand) break;
 
         // Display the transcription
-        setTranscriptionText(voiceCommand.text || '(No speech detected)');
+        setPartialTranscript(null);
+        if (voiceCommand.text) {
+          setTranscriptionText(voiceCommand.text);
+        } else if (voiceCommand.filtered.length > 0) {
+          const { text, reason } = voiceCommand.filtered[0];
+          setTranscriptionText(`(Ignored "${text}": ${reason.kind.replace(/_/g, ' ')})`);
+        } else {
+          setTranscriptionText('(No speech detected)');
+        }
 
         // Add to command history and log to file
         if (voiceCommand.text) {
@@ -215,48 +357,43 @@ function App() {
           }
         }
 
-        // Check if wake word was in the command
-        const text = voiceCommand.text.toLowerCase();
-        if (text.includes('kiku') || text.includes('computer')) {
-          setMessage({ type: 'info', text: 'Wake word detected!' });
+        // Process the command
+        const commandType = await invoke<CommandType | null>('process_voice_command', {
+          command: voiceCommand,
+        });
 
-          // Process the command
-          const commandType = await invoke<CommandType | null>('process_voice_command', {
-            command: voiceCommand,
+        if (commandType) {
+          const messageText =
+            COMMAND_MESSAGES[commandType] || `Command triggered: ${commandType}`;
+          setMessage({
+            type: 'command',
+            text: messageText,
+            commandType,
           });
-
-          if (commandType) {
-            const messageText =
-              COMMAND_MESSAGES[commandType] || `Command triggered: ${commandType}`;
-            setMessage({
-              type: 'command',
-              text: messageText,
-              commandType,
-            });
-          }
         }
-
-        // Small delay before next iteration
-        await new Promise(resolve => setTimeout(resolve, 500));
-      } catch (error) {
-        // Error in detection loop, log it and stop listening
-        console.error('Wake word detection error:', error);
+        break;
+      }
+      case 'cancelled':
+        setPartialTranscript(null);
+        setTranscriptionText('Say "kiku" or "computer" followed by your command...');
+        setMessage({ type: 'info', text: event.message });
+        break;
+      case 'error':
+        console.error('Wake word detection error:', event.message);
         setMessage({
           type: 'error',
-          text: `Wake word detection failed: ${error instanceof Error ? error.message : String(error)}`,
+          text: `Wake word detection failed: ${event.message}`,
         });
-
-        // Stop listening gracefully
-        try {
-          await invoke<string>('stop_background_listening');
-        } catch (stopError) {
-          console.error('Error stopping background listening:', stopError);
-        }
-
+        break;
+      case 'stopped':
+        unlistenRef.current?.();
+        unlistenRef.current = null;
+        unlistenPartialRef.current?.();
+        unlistenPartialRef.current = null;
+        setPartialTranscript(null);
         setIsListening(false);
         setTranscriptionText('Press "Start Listening" to begin...');
         break;
-      }
     }
   };
 
@@ -370,6 +507,13 @@ function App() {
             >
               Stop Listening
             </button>
+            <button
+              className="btn-base bg-gray-500 hover:bg-gray-600"
+              onClick={handleCancelTranscription}
+              disabled={!isListening}
+            >
+              Cancel Command
+            </button>
           </div>
         </div>
       )}
@@ -377,7 +521,16 @@ function App() {
       {/* Transcription Result */}
       <div className="mb-5 min-h-[100px] rounded-xl bg-white/10 p-5">
         <h3 className="mt-0 mb-3 text-xl font-semibold">Transcription Result:</h3>
-        <div className="min-h-[60px] text-lg leading-relaxed">{transcriptionText}</div>
+        <div className="min-h-[60px] text-lg leading-relaxed">
+          {partialTranscript ? (
+            <>
+              {partialTranscript.stable}{' '}
+              <span className="text-white/50">{partialTranscript.unstable}</span>
+            </>
+          ) : (
+            transcriptionText
+          )}
+        </div>
         {message && (
           <div
             className={`mt-4 rounded-xl p-4 ${
@@ -419,6 +572,8 @@ function App() {
                 <div className="flex items-start justify-between gap-2">
                   <span className="flex-1 break-words">{cmd.text}</span>
                   <span className="text-xs opacity-60 whitespace-nowrap">
+                    {cmd.language ? `${cmd.language.toUpperCase()} · ` : ''}
+                    {Math.round(cmd.confidence * 100)}% ·{' '}
                     {new Date(cmd.timestamp * 1000).toLocaleTimeString()}
                   </span>
                 </div>
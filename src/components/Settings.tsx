@@ -1,53 +1,28 @@
 import { useState, useEffect } from 'react';
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { open } from '@tauri-apps/plugin-dialog';
 import { Store } from '@tauri-apps/plugin-store';
-
-interface ModelInfo {
-  name: string;
-  size: string;
-  description: string;
-  filename: string;
-}
+import type {
+  DownloadProgress,
+  ModelInfo,
+  ModelList,
+  SpeechBackend,
+  TranscriptionOptions,
+  VadMode,
+} from '../types';
+import {
+  DEFAULT_SPEECH_BACKEND,
+  DEFAULT_TRANSCRIPTION_OPTIONS,
+  DEFAULT_VAD_MODE,
+  DEFAULT_VAD_THRESHOLD,
+} from '../types';
 
 interface AudioDevice {
   name: string;
   is_default: boolean;
 }
 
-const AVAILABLE_MODELS: ModelInfo[] = [
-  {
-    name: 'Tiny (English)',
-    size: '75 MB',
-    description: 'Fastest, lower accuracy',
-    filename: 'ggml-tiny.en.bin',
-  },
-  {
-    name: 'Base (English)',
-    size: '142 MB',
-    description: 'Best balance of speed/accuracy',
-    filename: 'ggml-base.en.bin',
-  },
-  {
-    name: 'Small (English)',
-    size: '466 MB',
-    description: 'Better transcription quality',
-    filename: 'ggml-small.en.bin',
-  },
-  {
-    name: 'Medium (English)',
-    size: '1.5 GB',
-    description: 'High accuracy',
-    filename: 'ggml-medium.en.bin',
-  },
-  {
-    name: 'Base (Multilingual)',
-    size: '142 MB',
-    description: 'Multilingual support',
-    filename: 'ggml-base.bin',
-  },
-];
-
 interface SettingsProps {
   onBack: () => void;
   modelPath: string;
@@ -55,17 +30,27 @@ interface SettingsProps {
 }
 
 export default function Settings({ onBack, modelPath, onModelPathChange }: SettingsProps) {
-  const [selectedModel, setSelectedModel] = useState<string>(
-    AVAILABLE_MODELS[1]?.filename ?? 'ggml-base.en.bin'
-  );
+  const [selectedModel, setSelectedModel] = useState<string>('ggml-base.en.bin');
   const [downloading, setDownloading] = useState<boolean>(false);
   const [downloadProgress, setDownloadProgress] = useState<number>(0);
+  const [downloadBaseUrl, setDownloadBaseUrl] = useState<string>('');
+  const [expectedSha256, setExpectedSha256] = useState<string>('');
   const [downloadedModels, setDownloadedModels] = useState<string[]>([]);
+  const [modelList, setModelList] = useState<ModelList | null>(null);
+  const [wakeModel, setWakeModel] = useState<string>('');
+  const [speechBackend, setSpeechBackend] = useState<SpeechBackend>(DEFAULT_SPEECH_BACKEND);
   const [statusMessage, setStatusMessage] = useState<string>('');
   const [customPath, setCustomPath] = useState<string>(modelPath);
   const [modelsDirectory, setModelsDirectory] = useState<string>('');
   const [audioDevices, setAudioDevices] = useState<AudioDevice[]>([]);
   const [selectedDevice, setSelectedDevice] = useState<string>('');
+  const [commandMode, setCommandMode] = useState<boolean>(false);
+  const [streaming, setStreaming] = useState<boolean>(true);
+  const [vadMode, setVadMode] = useState<VadMode>(DEFAULT_VAD_MODE);
+  const [customVocabulary, setCustomVocabulary] = useState<string>('');
+  const [transcriptionOptions, setTranscriptionOptions] = useState<TranscriptionOptions>(
+    DEFAULT_TRANSCRIPTION_OPTIONS
+  );
 
   // Load saved selected model and available models on mount
   useEffect(() => {
@@ -77,15 +62,57 @@ export default function Settings({ onBack, modelPath, onModelPathChange }: Setti
           setSelectedModel(savedModel);
         }
 
+        // Downloads come from Hugging Face unless a mirror is saved
+        const savedBaseUrl = await store.get<string>('downloadBaseUrl');
+        if (savedBaseUrl) {
+          setDownloadBaseUrl(savedBaseUrl);
+          await invoke('set_download_base_url', { baseUrl: savedBaseUrl });
+        }
+
         // Load saved audio device
         const savedDevice = await store.get<string>('audioDevice');
         if (savedDevice) {
           setSelectedDevice(savedDevice);
         }
 
-        // Load available models from AppData
-        const available = await invoke<string[]>('list_available_models');
-        setDownloadedModels(available);
+        // Load saved transcription options, filling in anything added since they were saved
+        const savedOptions = await store.get<Partial<TranscriptionOptions>>('transcriptionOptions');
+        if (savedOptions) {
+          setTranscriptionOptions({ ...DEFAULT_TRANSCRIPTION_OPTIONS, ...savedOptions });
+        }
+
+        const savedVocabulary = await store.get<string[]>('customVocabulary');
+        if (savedVocabulary) {
+          setCustomVocabulary(savedVocabulary.join('\n'));
+        }
+
+        const savedCommandMode = await store.get<boolean>('commandMode');
+        if (savedCommandMode !== null && savedCommandMode !== undefined) {
+          setCommandMode(savedCommandMode);
+        }
+
+        const savedWakeModel = await store.get<string>('wakeModel');
+        if (savedWakeModel) {
+          setWakeModel(savedWakeModel);
+        }
+
+        const savedBackend = await store.get<SpeechBackend>('speechBackend');
+        if (savedBackend) {
+          setSpeechBackend({ ...DEFAULT_SPEECH_BACKEND, ...savedBackend });
+        }
+
+        const savedStreaming = await store.get<boolean>('streaming');
+        if (savedStreaming !== null && savedStreaming !== undefined) {
+          setStreaming(savedStreaming);
+        }
+
+        const savedVadMode = await store.get<VadMode>('vadMode');
+        if (savedVadMode) {
+          setVadMode(savedVadMode);
+        }
+
+        // Load installed and downloadable models
+        await refreshModels();
 
         // Get models directory path
         const modelsDir = await invoke<string>('get_models_directory');
@@ -109,9 +136,30 @@ export default function Settings({ onBack, modelPath, onModelPathChange }: Setti
     void loadSettings();
   }, []);
 
+  // Track download progress reported by the backend
+  useEffect(() => {
+    const unlisten = listen<DownloadProgress>('download_progress', event => {
+      const { downloaded, total } = event.payload;
+      if (total) {
+        setDownloadProgress(Math.floor((downloaded / total) * 100));
+      }
+    });
+    return () => {
+      void unlisten.then(fn => fn());
+    };
+  }, []);
+
+  const refreshModels = async (): Promise<ModelList> => {
+    const models = await invoke<ModelList>('list_models');
+    setModelList(models);
+    setDownloadedModels(models.installed.map(model => model.file_name));
+    return models;
+  };
+
   // Handle model selection change and save to store
   const handleModelChange = async (newModel: string): Promise<void> => {
     setSelectedModel(newModel);
+    setExpectedSha256('');
     try {
       const store = await Store.load('settings.json');
       await store.set('selectedModel', newModel);
@@ -139,6 +187,90 @@ export default function Settings({ onBack, modelPath, onModelPathChange }: Setti
     }
   };
 
+  // Handle transcription option changes
+  const handleOptionsChange = async (changes: Partial<TranscriptionOptions>): Promise<void> => {
+    const options = { ...transcriptionOptions, ...changes };
+    setTranscriptionOptions(options);
+    try {
+      // Save to store
+      const store = await Store.load('settings.json');
+      await store.set('transcriptionOptions', options);
+      await store.save();
+
+      // Update the voice handler
+      await invoke('set_transcription_options', { options });
+      setStatusMessage('Transcription options updated');
+    } catch (error) {
+      console.error('Failed to set transcription options', error);
+      setStatusMessage(`Transcription options saved, will apply on initialization: ${error}`);
+    }
+  };
+
+  // Handle command mode toggle
+  const handleCommandModeChange = async (enabled: boolean): Promise<void> => {
+    setCommandMode(enabled);
+    try {
+      const store = await Store.load('settings.json');
+      await store.set('commandMode', enabled);
+      await store.save();
+
+      await invoke('set_command_mode', { enabled });
+      setStatusMessage(enabled ? 'Command mode enabled' : 'Command mode disabled');
+    } catch (error) {
+      console.error('Failed to set command mode', error);
+      setStatusMessage(`Command mode saved, will apply on initialization: ${error}`);
+    }
+  };
+
+  const handleStreamingChange = async (enabled: boolean): Promise<void> => {
+    setStreaming(enabled);
+    try {
+      const store = await Store.load('settings.json');
+      await store.set('streaming', enabled);
+      await store.save();
+
+      await invoke('set_streaming', { enabled });
+      setStatusMessage(enabled ? 'Live transcription enabled' : 'Live transcription disabled');
+    } catch (error) {
+      console.error('Failed to set live transcription', error);
+      setStatusMessage(`Live transcription saved, will apply on initialization: ${error}`);
+    }
+  };
+
+  const handleVadModeChange = async (mode: VadMode): Promise<void> => {
+    setVadMode(mode);
+    try {
+      const store = await Store.load('settings.json');
+      await store.set('vadMode', mode);
+      await store.save();
+
+      await invoke('set_vad_mode', { mode });
+      setStatusMessage('End of speech detection updated');
+    } catch (error) {
+      console.error('Failed to set end of speech detection', error);
+      setStatusMessage(`End of speech detection saved, will apply on initialization: ${error}`);
+    }
+  };
+
+  // Save the custom vocabulary, one term per line
+  const handleSaveVocabulary = async (): Promise<void> => {
+    const vocabulary = customVocabulary
+      .split('\n')
+      .map(term => term.trim())
+      .filter(term => term.length > 0);
+    try {
+      const store = await Store.load('settings.json');
+      await store.set('customVocabulary', vocabulary);
+      await store.save();
+
+      await invoke('set_custom_vocabulary', { vocabulary });
+      setStatusMessage('Custom vocabulary updated');
+    } catch (error) {
+      console.error('Failed to set custom vocabulary', error);
+      setStatusMessage(`Custom vocabulary saved, will apply on initialization: ${error}`);
+    }
+  };
+
   const handleDownload = async (): Promise<void> => {
     try {
       setDownloading(true);
@@ -147,14 +279,19 @@ export default function Settings({ onBack, modelPath, onModelPathChange }: Setti
 
       const result = await invoke<string>('download_model', {
         modelName: selectedModel,
+        sha256: expectedSha256.trim() || null,
       });
+      setExpectedSha256('');
 
       setStatusMessage(result);
       setDownloadProgress(100);
 
-      // Refresh available models list
-      const available = await invoke<string[]>('list_available_models');
-      setDownloadedModels(available);
+      // Refresh available models list and move the selection off the installed model
+      const models = await refreshModels();
+      const nextModel = models.available[0]?.file_name;
+      if (nextModel) {
+        setSelectedModel(nextModel);
+      }
 
       // Auto-update model path
       const newPath = await invoke<string>('get_model_path', { modelName: selectedModel });
@@ -167,6 +304,51 @@ export default function Settings({ onBack, modelPath, onModelPathChange }: Setti
     }
   };
 
+  // Stop the download, keeping what's been fetched so the next attempt resumes
+  const handleCancelDownload = async (): Promise<void> => {
+    try {
+      await invoke<boolean>('cancel_download', { modelName: selectedModel });
+    } catch (error) {
+      console.error('Failed to cancel download', error);
+    }
+  };
+
+  const handleSetDefaultModel = async (model: string): Promise<void> => {
+    try {
+      const path = await invoke<string>('set_default_model', { modelName: model });
+      setCustomPath(path);
+      await onModelPathChange(path);
+      await refreshModels();
+      setStatusMessage(`${model} will load on startup`);
+    } catch (error) {
+      setStatusMessage(`Failed to set default model: ${error}`);
+    }
+  };
+
+  const handleDeleteModel = async (model: string): Promise<void> => {
+    try {
+      const result = await invoke<string>('delete_model', { modelName: model });
+      await refreshModels();
+      setStatusMessage(result);
+    } catch (error) {
+      setStatusMessage(`Failed to delete model: ${error}`);
+    }
+  };
+
+  const handleSaveBaseUrl = async (): Promise<void> => {
+    try {
+      const baseUrl = downloadBaseUrl.trim() || null;
+      await invoke('set_download_base_url', { baseUrl });
+
+      const store = await Store.load('settings.json');
+      await store.set('downloadBaseUrl', baseUrl);
+      await store.save();
+      setStatusMessage(baseUrl ? `Downloading models from ${baseUrl}` : 'Downloading models from Hugging Face');
+    } catch (error) {
+      setStatusMessage(`Failed to set download URL: ${error}`);
+    }
+  };
+
   const handleBrowse = async (): Promise<void> => {
     try {
       // Extract directory from current path to use as default
@@ -195,6 +377,42 @@ export default function Settings({ onBack, modelPath, onModelPathChange }: Setti
     }
   };
 
+  // Pick a separate, usually smaller, model for wake word detection; empty uses the command model
+  const handleWakeModelChange = async (modelName: string): Promise<void> => {
+    setWakeModel(modelName);
+    try {
+      const store = await Store.load('settings.json');
+      await store.set('wakeModel', modelName || null);
+      await store.save();
+
+      const modelPath = modelName
+        ? await invoke<string>('get_model_path', { modelName })
+        : null;
+      const result = await invoke<string>('set_wake_model', { modelPath });
+      setStatusMessage(result);
+    } catch (error) {
+      console.error('Failed to set wake word model', error);
+      setStatusMessage(`Wake word model saved, will apply on initialization: ${error}`);
+    }
+  };
+
+  // Takes effect the next time the voice system initializes
+  const handleSaveBackend = async (): Promise<void> => {
+    if (speechBackend.kind === 'remote' && !/^https?:\/\//.test(speechBackend.base_url.trim())) {
+      setStatusMessage('Server URL must start with http:// or https://');
+      return;
+    }
+
+    try {
+      const store = await Store.load('settings.json');
+      await store.set('speechBackend', speechBackend);
+      await store.save();
+      setStatusMessage('Transcription backend saved, restart the app to apply');
+    } catch (error) {
+      setStatusMessage(`Failed to save transcription backend: ${error}`);
+    }
+  };
+
   const handleSavePath = async (): Promise<void> => {
     await onModelPathChange(customPath);
     setStatusMessage('Model path saved');
@@ -232,14 +450,28 @@ export default function Settings({ onBack, modelPath, onModelPathChange }: Setti
             disabled={downloading}
             className="w-full rounded-lg bg-white/10 px-4 py-2 text-white backdrop-blur-sm transition hover:bg-white/20 disabled:opacity-50"
           >
-            {AVAILABLE_MODELS.map(model => (
-              <option key={model.filename} value={model.filename} className="bg-gray-800">
-                {model.name} - {model.size} - {model.description}
+            {modelList?.available.map(model => (
+              <option key={model.file_name} value={model.file_name} className="bg-gray-800">
+                {model.name} - {model.size_mb} MB - {model.recommended_use}
               </option>
             ))}
           </select>
         </div>
 
+        {!modelList?.available.find(model => model.file_name === selectedModel)?.sha256 && (
+          <div className="mb-4">
+            <label className="mb-2 block text-sm font-medium">SHA-256</label>
+            <input
+              type="text"
+              value={expectedSha256}
+              onChange={e => setExpectedSha256(e.target.value)}
+              disabled={downloading}
+              placeholder="Needed for mirrors, which don't publish checksums"
+              className="w-full rounded-lg bg-white/10 px-4 py-2 font-mono text-sm backdrop-blur-sm transition hover:bg-white/20 disabled:opacity-50"
+            />
+          </div>
+        )}
+
         <button
           onClick={handleDownload}
           disabled={downloading}
@@ -257,9 +489,34 @@ export default function Settings({ onBack, modelPath, onModelPathChange }: Setti
               />
             </div>
             <p className="mt-2 text-center text-sm text-white/70">{downloadProgress}%</p>
+            <button
+              onClick={handleCancelDownload}
+              className="mt-2 w-full rounded-lg bg-white/20 px-4 py-2 text-sm font-medium transition hover:bg-white/30"
+            >
+              Cancel Download
+            </button>
           </div>
         )}
 
+        <div className="mt-4">
+          <label className="mb-2 block text-sm font-medium">Download Mirror</label>
+          <div className="flex flex-col gap-2 sm:flex-row">
+            <input
+              type="text"
+              value={downloadBaseUrl}
+              onChange={e => setDownloadBaseUrl(e.target.value)}
+              placeholder="https://huggingface.co/ggerganov/whisper.cpp/resolve/main"
+              className="flex-1 rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+            />
+            <button
+              onClick={handleSaveBaseUrl}
+              className="rounded-lg bg-white/20 px-6 py-2 text-sm font-medium transition hover:bg-white/30 sm:whitespace-nowrap"
+            >
+              Save
+            </button>
+          </div>
+        </div>
+
         {statusMessage && (
           <div className="mt-4 rounded-lg bg-white/5 p-3 text-sm">{statusMessage}</div>
         )}
@@ -299,6 +556,95 @@ export default function Settings({ onBack, modelPath, onModelPathChange }: Setti
         </button>
       </div>
 
+      {/* Transcription Backend Section */}
+      <div className="mt-6 rounded-2xl bg-white/5 p-4 sm:mt-8 sm:p-6">
+        <h2 className="mb-3 text-lg font-semibold sm:mb-4 sm:text-xl">Transcription Backend</h2>
+        <p className="mb-3 text-sm text-white/70 sm:mb-4">
+          Transcribe commands with the local model, or offload them to a server speaking the OpenAI
+          transcription API, such as faster-whisper-server
+        </p>
+
+        <div className="mb-4">
+          <label className="mb-2 block text-sm font-medium">Backend</label>
+          <select
+            value={speechBackend.kind}
+            onChange={e =>
+              setSpeechBackend({ ...speechBackend, kind: e.target.value as SpeechBackend['kind'] })
+            }
+            className="w-full rounded-lg bg-white/10 px-4 py-2 text-white backdrop-blur-sm transition hover:bg-white/20"
+          >
+            <option value="local" className="bg-gray-800">
+              Local Whisper model
+            </option>
+            <option value="remote" className="bg-gray-800">
+              Transcription server
+            </option>
+          </select>
+        </div>
+
+        {speechBackend.kind === 'remote' && (
+          <div className="mb-4 space-y-3">
+            <input
+              type="text"
+              value={speechBackend.base_url}
+              onChange={e => setSpeechBackend({ ...speechBackend, base_url: e.target.value })}
+              placeholder="http://192.168.1.20:8000"
+              className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+            />
+            <input
+              type="password"
+              value={speechBackend.api_key}
+              onChange={e => setSpeechBackend({ ...speechBackend, api_key: e.target.value })}
+              placeholder="API key (optional)"
+              className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+            />
+            <input
+              type="text"
+              value={speechBackend.model}
+              onChange={e => setSpeechBackend({ ...speechBackend, model: e.target.value })}
+              placeholder="Model (default whisper-1)"
+              className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+            />
+            <p className="text-xs text-white/60">
+              Pick a local wake word model below to keep wake word checks off the network
+            </p>
+          </div>
+        )}
+
+        <button
+          onClick={handleSaveBackend}
+          className="w-full rounded-xl bg-white/20 px-4 py-2.5 text-sm font-medium transition hover:bg-white/30 sm:px-6 sm:py-3 sm:text-base"
+        >
+          Save Backend
+        </button>
+      </div>
+
+      {/* Wake Word Model Section */}
+      <div className="mt-6 rounded-2xl bg-white/5 p-4 sm:mt-8 sm:p-6">
+        <h2 className="mb-3 text-lg font-semibold sm:mb-4 sm:text-xl">Wake Word Model</h2>
+        <p className="mb-3 text-sm text-white/70 sm:mb-4">
+          Listen for wake words with a smaller model and keep the model above for commands
+        </p>
+
+        <div className="mb-4">
+          <label className="mb-2 block text-sm font-medium">Wake Word Model</label>
+          <select
+            value={wakeModel}
+            onChange={e => void handleWakeModelChange(e.target.value)}
+            className="w-full rounded-lg bg-white/10 px-4 py-2 text-white backdrop-blur-sm transition hover:bg-white/20"
+          >
+            <option value="" className="bg-gray-800">
+              Same as command model
+            </option>
+            {downloadedModels.map(model => (
+              <option key={model} value={model} className="bg-gray-800">
+                {model}
+              </option>
+            ))}
+          </select>
+        </div>
+      </div>
+
       {/* Audio Device Selection */}
       <div className="mt-6 rounded-2xl bg-white/5 p-4 sm:mt-8 sm:p-6">
         <h2 className="mb-3 text-lg font-semibold sm:mb-4 sm:text-xl">Audio Input Device</h2>
@@ -331,17 +677,331 @@ export default function Settings({ onBack, modelPath, onModelPathChange }: Setti
         )}
       </div>
 
+      {/* Transcription Options */}
+      <div className="mt-6 rounded-2xl bg-white/5 p-4 sm:mt-8 sm:p-6">
+        <h2 className="mb-3 text-lg font-semibold sm:mb-4 sm:text-xl">Transcription</h2>
+        <p className="mb-3 text-sm text-white/70 sm:mb-4">
+          Whisper decoding parameters used for every transcription
+        </p>
+
+        <div className="mb-4 grid grid-cols-1 gap-4 sm:grid-cols-2">
+          <div>
+            <label className="mb-2 block text-sm font-medium">Language</label>
+            <input
+              type="text"
+              value={transcriptionOptions.language ?? ''}
+              onChange={e => void handleOptionsChange({ language: e.target.value.trim() || null })}
+              placeholder="Auto-detect"
+              className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+            />
+          </div>
+          <div>
+            <label className="mb-2 block text-sm font-medium">Threads</label>
+            <input
+              type="number"
+              min={1}
+              value={transcriptionOptions.threads}
+              onChange={e => void handleOptionsChange({ threads: Math.max(1, Number(e.target.value) || 1) })}
+              className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+            />
+          </div>
+          <div>
+            <label className="mb-2 block text-sm font-medium">Beam Width</label>
+            <input
+              type="number"
+              min={0}
+              value={transcriptionOptions.beam_size ?? 0}
+              onChange={e => {
+                const width = Number(e.target.value) || 0;
+                void handleOptionsChange({ beam_size: width > 1 ? width : null });
+              }}
+              className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+            />
+            <p className="mt-1 text-xs text-white/60">0 or 1 uses greedy sampling</p>
+          </div>
+          <div>
+            <label className="mb-2 block text-sm font-medium">Timeout (seconds)</label>
+            <input
+              type="number"
+              min={0}
+              value={transcriptionOptions.timeout_ms === null ? 0 : transcriptionOptions.timeout_ms / 1000}
+              onChange={e => {
+                const seconds = Number(e.target.value) || 0;
+                void handleOptionsChange({ timeout_ms: seconds > 0 ? Math.round(seconds * 1000) : null });
+              }}
+              className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+            />
+            <p className="mt-1 text-xs text-white/60">0 waits as long as transcription takes</p>
+          </div>
+          <div>
+            <label className="mb-2 block text-sm font-medium">Temperature / Fallback Step</label>
+            <div className="flex gap-2">
+              <input
+                type="number"
+                min={0}
+                max={1}
+                step={0.1}
+                value={transcriptionOptions.temperature}
+                onChange={e => void handleOptionsChange({ temperature: Number(e.target.value) || 0 })}
+                className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+              />
+              <input
+                type="number"
+                min={0}
+                max={1}
+                step={0.1}
+                value={transcriptionOptions.temperature_fallback}
+                onChange={e => void handleOptionsChange({ temperature_fallback: Number(e.target.value) || 0 })}
+                className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+              />
+            </div>
+          </div>
+        </div>
+
+        <label className="mb-4 flex items-start gap-2 text-sm">
+          <input
+            type="checkbox"
+            checked={commandMode}
+            onChange={e => void handleCommandModeChange(e.target.checked)}
+            className="mt-1"
+          />
+          <span>
+            Command mode
+            <span className="block text-xs text-white/60">
+              Restrict recorded commands to wake words and known command phrases
+            </span>
+          </span>
+        </label>
+
+        <label className="mb-4 flex items-start gap-2 text-sm">
+          <input
+            type="checkbox"
+            checked={streaming}
+            onChange={e => void handleStreamingChange(e.target.checked)}
+            className="mt-1"
+          />
+          <span>
+            Live transcription
+            <span className="block text-xs text-white/60">
+              Show partial text while a command is being spoken
+            </span>
+          </span>
+        </label>
+
+        <div className="mb-4">
+          <label className="mb-2 block text-sm font-medium">Initial Prompt</label>
+          <input
+            type="text"
+            value={transcriptionOptions.initial_prompt ?? ''}
+            onChange={e => void handleOptionsChange({ initial_prompt: e.target.value || null })}
+            placeholder="Optional context to prime the decoder"
+            className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+          />
+        </div>
+
+        <div className="flex flex-col gap-2 text-sm sm:flex-row sm:gap-6">
+          <label className="flex items-center gap-2">
+            <input
+              type="checkbox"
+              checked={transcriptionOptions.no_context}
+              onChange={e => void handleOptionsChange({ no_context: e.target.checked })}
+            />
+            No context
+          </label>
+          <label className="flex items-center gap-2">
+            <input
+              type="checkbox"
+              checked={transcriptionOptions.single_segment}
+              onChange={e => void handleOptionsChange({ single_segment: e.target.checked })}
+            />
+            Single segment
+          </label>
+          <label className="flex items-center gap-2">
+            <input
+              type="checkbox"
+              checked={transcriptionOptions.suppress_blank}
+              onChange={e => void handleOptionsChange({ suppress_blank: e.target.checked })}
+            />
+            Suppress blank
+          </label>
+          <label className="flex items-center gap-2">
+            <input
+              type="checkbox"
+              checked={transcriptionOptions.vocabulary_biasing}
+              onChange={e => void handleOptionsChange({ vocabulary_biasing: e.target.checked })}
+            />
+            Vocabulary biasing
+          </label>
+          <label className="flex items-center gap-2">
+            <input
+              type="checkbox"
+              checked={transcriptionOptions.word_timestamps}
+              onChange={e => void handleOptionsChange({ word_timestamps: e.target.checked })}
+            />
+            Word timestamps
+          </label>
+          <label className="flex items-center gap-2">
+            <input
+              type="checkbox"
+              checked={transcriptionOptions.filter_hallucinations}
+              onChange={e => void handleOptionsChange({ filter_hallucinations: e.target.checked })}
+            />
+            Filter hallucinations
+          </label>
+        </div>
+      </div>
+
+      {/* End of Speech Detection */}
+      <div className="mt-6 rounded-2xl bg-white/5 p-4 sm:mt-8 sm:p-6">
+        <h2 className="mb-3 text-lg font-semibold sm:mb-4 sm:text-xl">End of Speech Detection</h2>
+        <p className="mb-3 text-sm text-white/70 sm:mb-4">
+          How loud audio has to be to count as speech when deciding a command has finished
+        </p>
+
+        <div className="grid grid-cols-1 gap-4 sm:grid-cols-2">
+          <div>
+            <label className="mb-2 block text-sm font-medium">Threshold</label>
+            <select
+              value={vadMode.kind}
+              onChange={e =>
+                void handleVadModeChange(
+                  e.target.value === 'fixed'
+                    ? { kind: 'fixed', threshold: DEFAULT_VAD_THRESHOLD }
+                    : DEFAULT_VAD_MODE
+                )
+              }
+              className="w-full rounded-lg bg-white/10 px-4 py-2 text-white backdrop-blur-sm transition hover:bg-white/20"
+            >
+              <option value="adaptive" className="bg-gray-800">
+                Adapt to background noise
+              </option>
+              <option value="fixed" className="bg-gray-800">
+                Fixed level
+              </option>
+            </select>
+          </div>
+          {vadMode.kind === 'adaptive' ? (
+            <div>
+              <label className="mb-2 block text-sm font-medium">Margin Above Noise (dB)</label>
+              <input
+                type="number"
+                min={1}
+                max={40}
+                step={1}
+                value={vadMode.margin_db}
+                onChange={e =>
+                  void handleVadModeChange({ kind: 'adaptive', margin_db: Number(e.target.value) || 1 })
+                }
+                className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+              />
+              <p className="mt-1 text-xs text-white/60">Raise if background chatter keeps commands open</p>
+            </div>
+          ) : (
+            <div>
+              <label className="mb-2 block text-sm font-medium">Energy Threshold</label>
+              <input
+                type="number"
+                min={0.001}
+                max={1}
+                step={0.005}
+                value={vadMode.threshold}
+                onChange={e =>
+                  void handleVadModeChange({
+                    kind: 'fixed',
+                    threshold: Number(e.target.value) || DEFAULT_VAD_THRESHOLD,
+                  })
+                }
+                className="w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+              />
+              <p className="mt-1 text-xs text-white/60">RMS level, 0.02 suits a quiet room</p>
+            </div>
+          )}
+        </div>
+      </div>
+
+      {/* Custom Vocabulary */}
+      <div className="mt-6 rounded-2xl bg-white/5 p-4 sm:mt-8 sm:p-6">
+        <h2 className="mb-3 text-lg font-semibold sm:mb-4 sm:text-xl">Custom Vocabulary</h2>
+        <p className="mb-3 text-sm text-white/70 sm:mb-4">
+          Project names and jargon Whisper should listen for, one per line, most important first
+        </p>
+
+        <textarea
+          value={customVocabulary}
+          onChange={e => setCustomVocabulary(e.target.value)}
+          rows={5}
+          placeholder={'Hyperion\nKubernetes'}
+          className="mb-4 w-full rounded-lg bg-white/10 px-4 py-2 text-sm backdrop-blur-sm transition hover:bg-white/20"
+        />
+
+        <button
+          onClick={handleSaveVocabulary}
+          className="w-full rounded-xl bg-white/20 px-4 py-2.5 text-sm font-medium transition hover:bg-white/30 sm:px-6 sm:py-3 sm:text-base"
+        >
+          Save Vocabulary
+        </button>
+      </div>
+
       {/* Downloaded Models */}
       {downloadedModels.length > 0 && (
         <div className="mt-6 rounded-2xl bg-white/5 p-4 sm:mt-8 sm:p-6">
           <h2 className="mb-3 text-lg font-semibold sm:mb-4 sm:text-xl">Downloaded Models</h2>
+          {modelList && (
+            <p className="mb-3 text-xs text-white/60 sm:mb-4">
+              Using {(modelList.disk_usage_bytes / 1024 / 1024).toFixed(0)} MB of disk space
+            </p>
+          )}
           <ul className="space-y-2">
-            {downloadedModels.map(model => (
-              <li key={model} className="flex items-center gap-2 break-all text-sm">
-                <span className="text-green-400">✓</span>
-                <span>{model}</span>
-              </li>
-            ))}
+            {modelList?.installed.map(installed => {
+              const model = installed.file_name;
+              // Header details, or why the file can't be used
+              const details: ModelInfo | string | null = installed.info ?? installed.info_error;
+              return (
+                <li key={model} className="flex items-start gap-2 break-all text-sm">
+                  {typeof details === 'string' ? (
+                    <span className="text-red-400">✗</span>
+                  ) : (
+                    <span className="text-green-400">✓</span>
+                  )}
+                  <span className="flex-1">
+                    {installed.catalog?.name ?? model}
+                    {installed.is_default && (
+                      <span className="ml-2 rounded bg-white/20 px-1.5 py-0.5 text-xs">Default</span>
+                    )}
+                    {details && (
+                      <span className="block text-xs text-white/60">
+                        {typeof details === 'string'
+                          ? details
+                          : [
+                              details.model_type ?? 'unknown size',
+                              details.multilingual ? 'multilingual' : 'English-only',
+                              details.quantization,
+                              `${details.vocab_size} tokens`,
+                              `${(details.file_size / 1024 / 1024).toFixed(0)} MB`,
+                              details.format === 'gguf' ? 'GGUF, not supported yet' : null,
+                            ]
+                              .filter(Boolean)
+                              .join(' · ')}
+                      </span>
+                    )}
+                  </span>
+                  {!installed.is_default && (
+                    <button
+                      onClick={() => void handleSetDefaultModel(model)}
+                      className="rounded-lg bg-white/20 px-3 py-1 text-xs font-medium transition hover:bg-white/30"
+                    >
+                      Set Default
+                    </button>
+                  )}
+                  <button
+                    onClick={() => void handleDeleteModel(model)}
+                    className="rounded-lg bg-white/20 px-3 py-1 text-xs font-medium transition hover:bg-red-500/60"
+                  >
+                    Delete
+                  </button>
+                </li>
+              );
+            })}
           </ul>
         </div>
       )}
@@ -9,6 +9,230 @@ export interface VoiceCommand {
   text: string;
   confidence: number;
   timestamp: number;
+  /** Language code the command was spoken in, when known */
+  language: string | null;
+  /** Text the hallucination filter dropped, and why */
+  filtered: FilteredText[];
+}
+
+/**
+ * Where speech is transcribed: a local Whisper model or an OpenAI-compatible server
+ */
+export interface SpeechBackend {
+  kind: 'local' | 'remote';
+  /** Server root, such as http://192.168.1.20:8000 */
+  base_url: string;
+  api_key: string;
+  /** Model name sent to the server, empty for the server's default */
+  model: string;
+}
+
+export const DEFAULT_SPEECH_BACKEND: SpeechBackend = {
+  kind: 'local',
+  base_url: '',
+  api_key: '',
+  model: '',
+};
+
+/**
+ * How the end of a spoken command is detected: a fixed RMS energy threshold, or a margin
+ * above the background noise the microphone picks up
+ */
+export type VadMode = { kind: 'fixed'; threshold: number } | { kind: 'adaptive'; margin_db: number };
+
+export const DEFAULT_VAD_MODE: VadMode = { kind: 'adaptive', margin_db: 10 };
+
+/** Threshold the fixed mode starts from, the one used before the noise floor was tracked */
+export const DEFAULT_VAD_THRESHOLD = 0.02;
+
+/**
+ * Why the hallucination filter dropped some text
+ */
+export type FilterReason =
+  /** Only reported by the HTTP backend */
+  | { kind: 'no_speech'; probability: number }
+  | { kind: 'repetition'; compression_ratio: number }
+  | { kind: 'known_hallucination' }
+  | { kind: 'non_speech' };
+
+export interface FilteredText {
+  text: string;
+  reason: FilterReason;
+}
+
+/**
+ * Phrases that trigger an action, defined per language
+ */
+export interface CommandMapping {
+  action: string;
+  phrases: string[];
+}
+
+/**
+ * Event emitted by the background listener
+ */
+export interface ListeningEvent {
+  event_type: 'listening' | 'wake_word_detected' | 'command' | 'cancelled' | 'error' | 'stopped';
+  message: string;
+  command: VoiceCommand | null;
+}
+
+/**
+ * Payload of the download_progress event
+ */
+export interface DownloadProgress {
+  model_name: string;
+  /** Bytes on disk so far, including any resumed from an earlier attempt */
+  downloaded: number;
+  /** Full size, when the server reports it */
+  total: number | null;
+}
+
+/**
+ * A model in the built-in catalogue
+ */
+export interface CatalogEntry {
+  file_name: string;
+  name: string;
+  size_mb: number;
+  multilingual: boolean;
+  quantization: string;
+  recommended_use: string;
+  sha256: string | null;
+}
+
+/**
+ * A model file in the models directory
+ */
+export interface InstalledModel {
+  file_name: string;
+  size_bytes: number;
+  /** Loaded on startup */
+  is_default: boolean;
+  /** null for models that didn't come from the catalogue */
+  catalog: CatalogEntry | null;
+  /** What the header says, null when it couldn't be read */
+  info: ModelInfo | null;
+  /** Why the header couldn't be read */
+  info_error: string | null;
+}
+
+/**
+ * Installed models and the catalogue models not downloaded yet
+ */
+export interface ModelList {
+  installed: InstalledModel[];
+  available: CatalogEntry[];
+  disk_usage_bytes: number;
+}
+
+/**
+ * What a model file's header says about it
+ */
+export interface ModelInfo {
+  format: 'ggml' | 'gguf';
+  /** "tiny", "base", "small", "medium" or "large", null when unrecognised */
+  model_type: string | null;
+  multilingual: boolean;
+  /** Weight type such as "f16" or "q5_0" */
+  quantization: string;
+  vocab_size: number;
+  file_size: number;
+}
+
+/**
+ * Progress of swapping in a new command model
+ */
+export interface ModelSwitchEvent {
+  status: 'loading' | 'loaded' | 'failed';
+  model_path: string;
+  message: string;
+}
+
+/**
+ * Partial transcription emitted while a command is being recorded
+ */
+export interface PartialTranscript {
+  /** Words that have settled and won't be retracted */
+  stable: string;
+  /** The rest of the latest decode, likely to change */
+  unstable: string;
+  /** Set on the last event of an utterance, carrying the final transcription */
+  is_final: boolean;
+}
+
+/**
+ * Whisper decoding parameters, persisted in settings and applied on initialization
+ */
+export interface TranscriptionOptions {
+  /** Language code such as "en", or null to auto-detect */
+  language: string | null;
+  threads: number;
+  /** Beam search width, or null for greedy sampling */
+  beam_size: number | null;
+  temperature: number;
+  /** Temperature increase on decode failure, 0 disables fallback */
+  temperature_fallback: number;
+  initial_prompt: string | null;
+  no_context: boolean;
+  single_segment: boolean;
+  suppress_blank: boolean;
+  /** Estimate per-word timings, at some extra decoding cost */
+  word_timestamps: boolean;
+  /** Append wake words, command phrases and custom vocabulary to the initial prompt */
+  vocabulary_biasing: boolean;
+  /** Give up on a transcription after this many milliseconds, or null to wait forever */
+  timeout_ms: number | null;
+  /** Drop annotations, stock phrases and repetition Whisper makes up from silence */
+  filter_hallucinations: boolean;
+}
+
+export const DEFAULT_TRANSCRIPTION_OPTIONS: TranscriptionOptions = {
+  language: 'en',
+  threads: 4,
+  beam_size: null,
+  temperature: 0,
+  temperature_fallback: 0.2,
+  initial_prompt: null,
+  no_context: true,
+  single_segment: false,
+  suppress_blank: true,
+  word_timestamps: false,
+  vocabulary_biasing: true,
+  timeout_ms: null,
+  filter_hallucinations: true,
+};
+
+/**
+ * Timed transcription returned by transcribe_file_detailed
+ */
+export interface Transcription {
+  text: string;
+  confidence: number;
+  language: string;
+  /** Null unless the language was detected from the audio */
+  language_probability: number | null;
+  segments: TranscriptSegment[];
+  /** Segments the hallucination filter left out */
+  filtered: FilteredText[];
+}
+
+export interface TranscriptSegment {
+  start_ms: number;
+  end_ms: number;
+  text: string;
+  avg_prob: number;
+  /** Null when the backend doesn't report it */
+  no_speech_prob: number | null;
+  /** Empty unless word timestamps were requested */
+  words: TranscriptWord[];
+}
+
+export interface TranscriptWord {
+  text: string;
+  start_ms: number;
+  end_ms: number;
+  probability: number;
 }
 
 /**